@@ -0,0 +1,4345 @@
+//! Line counting behind the rcloc binary. `analyze_path` counts a
+//! directory like `rcloc PATH` does; `FileAnalyzer::classify_line` classifies
+//! lines one at a time, e.g. as an editor buffer changes.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Result, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use clap::{Arg, ArgAction, Command};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageConfig {
+    pub name: String,
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub line_comment: Vec<String>,
+    #[serde(default)]
+    pub block_comment_start: Vec<String>,
+    #[serde(default)]
+    pub block_comment_end: Vec<String>,
+    // Opening and closing tokens of literals whose contents never start a comment
+    #[serde(default)]
+    pub string_delimiters: Vec<(String, String)>,
+}
+
+// Shape of a --config file: a list of [[language]] tables in TOML, or a
+// "language" array in JSON
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LanguageConfigFile {
+    #[serde(default, rename = "language")]
+    languages: Vec<LanguageConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FileStats {
+    pub files: u64,
+    pub blank_lines: u64,
+    pub comment_lines: u64,
+    pub code_lines: u64,
+    #[serde(skip)]
+    pub stub_functions: u64,
+    // Files with comments but no code, such as license headers
+    #[serde(skip)]
+    pub comment_only_files: u64,
+}
+
+impl LanguageConfig {
+    // Rejects definitions classify_line can't handle: every block comment
+    // start needs a matching end, and no token may be empty since an empty
+    // token matches everywhere without consuming input
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.extensions.is_empty() {
+            return Err(format!("{}: at least one extension is required", self.name));
+        }
+        for ext in &self.extensions {
+            if ext.is_empty() || ext.starts_with('.') {
+                return Err(format!("{}: invalid extension {:?} (write extensions without the leading dot)", self.name, ext));
+            }
+        }
+        
+        if self.block_comment_start.len() != self.block_comment_end.len() {
+            return Err(format!(
+                "{}: {} block comment start tokens but {} end tokens",
+                self.name, self.block_comment_start.len(), self.block_comment_end.len()
+            ));
+        }
+        
+        let tokens = self.line_comment.iter()
+            .chain(&self.block_comment_start)
+            .chain(&self.block_comment_end)
+            .chain(self.string_delimiters.iter().flat_map(|(open, close)| [open, close]));
+        for token in tokens {
+            if token.is_empty() {
+                return Err(format!("{}: comment and string tokens must not be empty", self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::Add for FileStats {
+    type Output = Self;
+    
+    // Counts saturate at u64::MAX instead of overflowing, so aggregation
+    // never panics
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl std::ops::AddAssign for FileStats {
+    fn add_assign(&mut self, other: Self) {
+        self.files = self.files.saturating_add(other.files);
+        self.blank_lines = self.blank_lines.saturating_add(other.blank_lines);
+        self.comment_lines = self.comment_lines.saturating_add(other.comment_lines);
+        self.code_lines = self.code_lines.saturating_add(other.code_lines);
+        self.stub_functions = self.stub_functions.saturating_add(other.stub_functions);
+        self.comment_only_files = self.comment_only_files.saturating_add(other.comment_only_files);
+    }
+}
+
+// Signed change between two FileStats, as produced by `new - old`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct FileStatsDelta {
+    pub files: i64,
+    pub blank_lines: i64,
+    pub comment_lines: i64,
+    pub code_lines: i64,
+}
+
+impl std::ops::Sub for FileStats {
+    type Output = FileStatsDelta;
+    
+    fn sub(self, other: Self) -> FileStatsDelta {
+        FileStatsDelta {
+            files: self.files as i64 - other.files as i64,
+            blank_lines: self.blank_lines as i64 - other.blank_lines as i64,
+            comment_lines: self.comment_lines as i64 - other.comment_lines as i64,
+            code_lines: self.code_lines as i64 - other.code_lines as i64,
+        }
+    }
+}
+
+fn string_pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(open, close)| (open.to_string(), close.to_string())).collect()
+}
+
+pub struct LanguageDatabase {
+    languages: HashMap<String, Arc<LanguageConfig>>,
+    ext_to_lang: HashMap<String, String>,
+    // Languages of individual files from --lang-manifest, keyed by
+    // normalized path; these win over extension detection
+    path_to_lang: HashMap<PathBuf, String>,
+}
+
+// `path` without `.` components, so ./src/a.rs and src/a.rs compare equal
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components().filter(|component| *component != Component::CurDir).collect()
+}
+
+impl Default for LanguageDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LanguageDatabase {
+    pub fn new() -> Self {
+        let mut db = LanguageDatabase {
+            languages: HashMap::new(),
+            ext_to_lang: HashMap::new(),
+            path_to_lang: HashMap::new(),
+        };
+        
+        db.add_languages().expect("built-in language definitions are valid");
+        db
+    }
+    
+    // A language added later wins: redefining a name replaces the old
+    // definition, and an extension claimed by another language moves to the
+    // new one. A language left without extensions is dropped.
+    fn add_language(&mut self, config: LanguageConfig) -> std::result::Result<(), String> {
+        config.validate()?;
+        if let Some(old) = self.languages.remove(&config.name) {
+            for ext in &old.extensions {
+                if self.ext_to_lang.get(ext) == Some(&old.name) {
+                    self.ext_to_lang.remove(ext);
+                }
+            }
+        }
+        
+        for ext in &config.extensions {
+            let Some(previous) = self.ext_to_lang.insert(ext.clone(), config.name.clone()) else {
+                continue;
+            };
+            let orphaned = match self.languages.get_mut(&previous) {
+                Some(lang) => {
+                    let lang = Arc::make_mut(lang);
+                    lang.extensions.retain(|e| e != ext);
+                    lang.extensions.is_empty()
+                }
+                None => false,
+            };
+            if orphaned {
+                self.languages.remove(&previous);
+            }
+        }
+        self.languages.insert(config.name.clone(), Arc::new(config));
+        Ok(())
+    }
+    
+    // Adds the languages from a --config file on top of the built-ins. The
+    // file is parsed as JSON when its extension is .json and as TOML otherwise.
+    pub fn load_config(&mut self, path: &Path) -> std::result::Result<(), String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let file: LanguageConfigFile = if is_json {
+            serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        };
+        self.add_language_file(path, file.languages)
+    }
+    
+    // Adds the languages defined in the config file at `path`
+    fn add_language_file(&mut self, path: &Path, languages: Vec<LanguageConfig>) -> std::result::Result<(), String> {
+        // Within one file a collision is a mistake rather than an override
+        let mut claimed: HashMap<&str, &str> = HashMap::new();
+        for lang in &languages {
+            for ext in &lang.extensions {
+                if let Some(other) = claimed.insert(ext, &lang.name) {
+                    return Err(format!(
+                        "{}: extension .{} is defined by both {} and {}",
+                        path.display(), ext, other, lang.name
+                    ));
+                }
+            }
+        }
+        
+        for lang in languages {
+            self.add_language(lang).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+    
+    // Loads a JSON object mapping file paths to language names, as written
+    // by a build system that already knows them. Relative paths are
+    // resolved against `root`, an analyzed directory; loading the manifest
+    // for several roots applies it to each of them.
+    pub fn load_manifest(&mut self, path: &Path, root: &Path) -> std::result::Result<(), String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let entries: HashMap<String, String> = serde_json::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        
+        let base = if root.is_dir() { root } else { root.parent().unwrap_or(Path::new("")) };
+        for (file, lang) in entries {
+            if !self.languages.contains_key(&lang) {
+                return Err(format!("{}: unknown language {:?} for {}", path.display(), lang, file));
+            }
+            self.path_to_lang.insert(normalize_path(&base.join(file)), lang);
+        }
+        Ok(())
+    }
+    
+    // Every known language and its extensions, sorted by name
+    fn print_languages(&self) {
+        let mut languages: Vec<_> = self.languages.values().collect();
+        languages.sort_by(|a, b| a.name.cmp(&b.name));
+        
+        println!("{:<20} Extensions", "Language");
+        println!("{}", "-".repeat(70));
+        for lang in languages {
+            let mut extensions: Vec<String> = lang.extensions.iter().map(|ext| format!(".{}", ext)).collect();
+            extensions.sort();
+            println!("{:<20} {}", lang.name, extensions.join(", "));
+        }
+    }
+    
+    fn add_languages(&mut self) -> std::result::Result<(), String> {
+        // Quote styles shared by most C-like and scripting languages
+        let c_strings = string_pairs(&[("\"", "\""), ("'", "'")]);
+        let template_strings = string_pairs(&[("\"", "\""), ("'", "'"), ("`", "`")]);
+        
+        // Rust. Single quotes also mark lifetimes, so only the '"' char
+        // literal is treated as a string; raw strings close on "# / "##.
+        self.add_language(LanguageConfig {
+            name: "Rust".to_string(),
+            extensions: vec!["rs".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: string_pairs(&[("r##\"", "\"##"), ("r#\"", "\"#"), ("'\"", "'"), ("\"", "\"")]),
+        })?;
+        
+        // C/C++
+        self.add_language(LanguageConfig {
+            name: "C/C++".to_string(),
+            extensions: vec!["c".to_string(), "cpp".to_string(), "cc".to_string(), "cxx".to_string(), "h".to_string(), "hpp".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // Python
+        self.add_language(LanguageConfig {
+            name: "Python".to_string(),
+            extensions: vec!["py".to_string(), "pyw".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec!["\"\"\"".to_string(), "'''".to_string()],
+            block_comment_end: vec!["\"\"\"".to_string(), "'''".to_string()],
+            string_delimiters: string_pairs(&[("\"\"\"", "\"\"\""), ("'''", "'''"), ("\"", "\""), ("'", "'")]),
+        })?;
+        
+        // JavaScript/TypeScript
+        self.add_language(LanguageConfig {
+            name: "JavaScript".to_string(),
+            extensions: vec!["js".to_string(), "jsx".to_string(), "mjs".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: template_strings.clone(),
+        })?;
+        
+        self.add_language(LanguageConfig {
+            name: "TypeScript".to_string(),
+            extensions: vec!["ts".to_string(), "tsx".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: template_strings.clone(),
+        })?;
+        
+        // Java
+        self.add_language(LanguageConfig {
+            name: "Java".to_string(),
+            extensions: vec!["java".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // C#
+        self.add_language(LanguageConfig {
+            name: "C#".to_string(),
+            extensions: vec!["cs".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // Go
+        self.add_language(LanguageConfig {
+            name: "Go".to_string(),
+            extensions: vec!["go".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: template_strings,
+        })?;
+        
+        // Shell scripts
+        self.add_language(LanguageConfig {
+            name: "Shell".to_string(),
+            extensions: vec!["sh".to_string(), "bash".to_string(), "zsh".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // PowerShell
+        self.add_language(LanguageConfig {
+            name: "PowerShell".to_string(),
+            extensions: vec!["ps1".to_string(), "psm1".to_string(), "psd1".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec!["<#".to_string()],
+            block_comment_end: vec!["#>".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // HTML/XML
+        self.add_language(LanguageConfig {
+            name: "HTML".to_string(),
+            extensions: vec!["html".to_string(), "htm".to_string(), "xml".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec!["<!--".to_string()],
+            block_comment_end: vec!["-->".to_string()],
+            string_delimiters: vec![],
+        })?;
+        
+        // CSS, plus SCSS and LESS which also allow // line comments. Quoted
+        // strings and unquoted url(...) values may contain "//" or "/*".
+        let css_strings = string_pairs(&[("\"", "\""), ("'", "'"), ("url(", ")")]);
+        
+        self.add_language(LanguageConfig {
+            name: "CSS".to_string(),
+            extensions: vec!["css".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: css_strings.clone(),
+        })?;
+        
+        self.add_language(LanguageConfig {
+            name: "SCSS".to_string(),
+            extensions: vec!["scss".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: css_strings.clone(),
+        })?;
+        
+        self.add_language(LanguageConfig {
+            name: "LESS".to_string(),
+            extensions: vec!["less".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: css_strings,
+        })?;
+        
+        // SQL
+        self.add_language(LanguageConfig {
+            name: "SQL".to_string(),
+            extensions: vec!["sql".to_string()],
+            line_comment: vec!["--".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // Ruby
+        self.add_language(LanguageConfig {
+            name: "Ruby".to_string(),
+            extensions: vec!["rb".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec!["=begin".to_string()],
+            block_comment_end: vec!["=end".to_string()],
+            string_delimiters: c_strings.clone(),
+        })?;
+        
+        // PHP
+        self.add_language(LanguageConfig {
+            name: "PHP".to_string(),
+            extensions: vec!["php".to_string()],
+            line_comment: vec!["//".to_string(), "#".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: c_strings,
+        })?;
+        
+        // YAML/JSON
+        self.add_language(LanguageConfig {
+            name: "YAML".to_string(),
+            extensions: vec!["yaml".to_string(), "yml".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: vec![],
+        })?;
+        
+        // JSON has no comments; a "//" in a value like "http://x" is code
+        self.add_language(LanguageConfig {
+            name: "JSON".to_string(),
+            extensions: vec!["json".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        // Markdown
+        self.add_language(LanguageConfig {
+            name: "Markdown".to_string(),
+            extensions: vec!["md".to_string(), "markdown".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec!["<!--".to_string()],
+            block_comment_end: vec!["-->".to_string()],
+            string_delimiters: vec![],
+        })?;
+        
+        // Assembly. Raw .s/.asm sources only have assembler comments, while .S
+        // goes through the C preprocessor first, so it also has C comments and
+        // its # lines are directives such as #include rather than comments.
+        self.add_language(LanguageConfig {
+            name: "Assembly".to_string(),
+            extensions: vec!["s".to_string(), "asm".to_string()],
+            line_comment: vec![";".to_string(), "#".to_string()],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        self.add_language(LanguageConfig {
+            name: "Assembly (cpp)".to_string(),
+            extensions: vec!["S".to_string()],
+            line_comment: vec!["//".to_string(), ";".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        // OCaml, including ocamllex (.mll) and ocamlyacc/Menhir (.mly) sources
+        self.add_language(LanguageConfig {
+            name: "OCaml".to_string(),
+            extensions: vec!["ml".to_string(), "mli".to_string(), "mll".to_string(), "mly".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec!["(*".to_string()],
+            block_comment_end: vec!["*)".to_string()],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        // ReasonML and ReScript share C-style comments but are distinct languages
+        self.add_language(LanguageConfig {
+            name: "ReasonML".to_string(),
+            extensions: vec!["re".to_string(), "rei".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        self.add_language(LanguageConfig {
+            name: "ReScript".to_string(),
+            extensions: vec!["res".to_string(), "resi".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec!["/*".to_string()],
+            block_comment_end: vec!["*/".to_string()],
+            string_delimiters: string_pairs(&[("\"", "\""), ("`", "`")]),
+        })?;
+        
+        // Gettext translations; msgid/msgstr entries count as code
+        self.add_language(LanguageConfig {
+            name: "Gettext".to_string(),
+            extensions: vec!["po".to_string(), "pot".to_string()],
+            line_comment: vec!["#".to_string()],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        })?;
+        
+        // Tabular data; no comment syntax, so every non-blank row counts as code
+        self.add_language(LanguageConfig {
+            name: "CSV".to_string(),
+            extensions: vec!["csv".to_string(), "tsv".to_string()],
+            line_comment: vec![],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: vec![],
+        })?;
+        
+        Ok(())
+    }
+    
+    // Paths from --lang-manifest are looked up first. Then an exact-case
+    // match wins so extensions like .S and .s can map to different
+    // languages; otherwise extensions match case-insensitively.
+    pub fn get_language(&self, path: &Path) -> Option<&Arc<LanguageConfig>> {
+        if !self.path_to_lang.is_empty() {
+            if let Some(lang_name) = self.path_to_lang.get(&normalize_path(path)) {
+                return self.languages.get(lang_name);
+            }
+        }
+        
+        let ext = path.extension()?.to_str()?;
+        let lang_name = self.ext_to_lang.get(ext)
+            .or_else(|| self.ext_to_lang.get(&ext.to_lowercase()))?;
+        self.languages.get(lang_name)
+    }
+}
+
+// What analyze_file found: line counts, or a file that only has a
+// source extension but holds binary data
+#[derive(Debug)]
+enum FileOutcome {
+    Counted(FileStats),
+    Binary,
+}
+
+// Bytes inspected by looks_binary, which also fits the BufReader buffer
+const BINARY_SNIFF_LEN: usize = 8192;
+
+// A NUL byte, or more than 30% control characters and invalid UTF-8, in
+// the first chunk marks a file as binary, similar to git's heuristic
+fn looks_binary(chunk: &[u8]) -> bool {
+    if chunk.contains(&0) {
+        return true;
+    }
+    
+    let control = chunk.iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || b == 0x7f)
+        .count();
+    let mut invalid = 0;
+    let mut rest = chunk;
+    // A sequence cut off at the end of the chunk is not counted
+    while let Err(e) = std::str::from_utf8(rest) {
+        let Some(len) = e.error_len() else { break };
+        invalid += len;
+        rest = &rest[e.valid_up_to() + len..];
+    }
+    (control + invalid) * 10 > chunk.len() * 3
+}
+
+// A `#!` interpreter line at the top of a script selects how the file runs,
+// so it counts as code even in languages where `#` starts a comment. Only
+// the first line is checked; `#!` anywhere else is classified normally.
+fn is_shebang(first_line: &str) -> bool {
+    first_line.starts_with("#!")
+}
+
+// Block comment state carried by classify_line from one line to the next:
+// how many block comments are open and the token that closes the innermost
+// one. Comments don't nest yet, so depth is 0 or 1.
+#[derive(Debug, Clone, Default)]
+pub struct BlockState {
+    depth: usize,
+    end: String,
+}
+
+impl BlockState {
+    pub fn in_comment(&self) -> bool {
+        self.depth > 0
+    }
+    
+    fn open(&mut self, end: String) {
+        self.depth = 1;
+        self.end = end;
+    }
+    
+    fn close(&mut self) {
+        self.depth = 0;
+        self.end.clear();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LineType {
+    Blank,
+    Comment,
+    Code,
+}
+
+pub struct FileAnalyzer {
+    lang_config: Arc<LanguageConfig>,
+}
+
+impl FileAnalyzer {
+    pub fn new(lang_config: Arc<LanguageConfig>) -> Self {
+        Self { lang_config }
+    }
+    
+    fn analyze_file(&self, path: &Path) -> Result<FileOutcome> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::with_capacity(BINARY_SNIFF_LEN, file);
+        if looks_binary(reader.fill_buf()?) {
+            return Ok(FileOutcome::Binary);
+        }
+        self.analyze_reader(reader).map(FileOutcome::Counted)
+    }
+    
+    pub fn analyze_reader<R: BufRead>(&self, reader: R) -> Result<FileStats> {
+        let mut stats = FileStats {
+            files: 1,
+            ..Default::default()
+        };
+        
+        let mut block_state = BlockState::default();
+        
+        for (index, line_result) in reader.lines().enumerate() {
+            let line = line_result?;
+            match self.classify_file_line(index, &line, &mut block_state) {
+                LineType::Blank => stats.blank_lines += 1,
+                LineType::Comment => stats.comment_lines += 1,
+                LineType::Code => stats.code_lines += 1,
+            }
+        }
+        
+        if stats.code_lines == 0 && stats.comment_lines > 0 {
+            stats.comment_only_files = 1;
+        }
+        Ok(stats)
+    }
+    
+    fn find_functions(&self, path: &Path) -> Result<Vec<FunctionSpan>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(self.find_functions_in(&content))
+    }
+    
+    fn find_functions_in(&self, content: &str) -> Vec<FunctionSpan> {
+        let lines: Vec<&str> = content.lines().collect();
+        find_functions(&lines, &self.lang_config)
+    }
+    
+    // Position of the first `token` in `text` that is not inside a string
+    fn find_token(&self, text: &str, token: &str) -> Option<usize> {
+        if self.lang_config.string_delimiters.is_empty() {
+            return text.find(token);
+        }
+        
+        let mut pos = 0;
+        while pos < text.len() {
+            let rest = &text[pos..];
+            if rest.starts_with(token) {
+                return Some(pos);
+            }
+            
+            // An empty opening delimiter would match everywhere without
+            // moving past anything; validate() rejects it, but never loop on it
+            let string = self.lang_config.string_delimiters.iter()
+                .find(|(open, _)| !open.is_empty() && rest.starts_with(open.as_str()));
+            if let Some((open, close)) = string {
+                pos += open.len();
+                // An unterminated string hides the rest of the line
+                let end = find_string_end(&text[pos..], close)?;
+                pos += end + close.len();
+                continue;
+            }
+            
+            pos += rest.chars().next().map_or(1, char::len_utf8);
+        }
+        None
+    }
+    
+    // Classifies the `index`th (0-based) untrimmed line of a file. A leading
+    // UTF-8 BOM is not content, so a BOM-only first line is blank, and a
+    // first-line shebang is code.
+    pub fn classify_file_line(&self, index: usize, line: &str, state: &mut BlockState) -> LineType {
+        let line = if index == 0 { line.trim_start_matches('\u{feff}') } else { line };
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            LineType::Blank
+        } else if index == 0 && is_shebang(trimmed) {
+            LineType::Code
+        } else {
+            self.classify_line(trimmed, state)
+        }
+    }
+    
+    // Classifies one trimmed, non-empty line. Callers feed a file's lines in
+    // order with the same BlockState, starting from BlockState::default().
+    pub fn classify_line(&self, line: &str, state: &mut BlockState) -> LineType {
+        // Data formats such as JSON and CSV have no comment syntax, so any
+        // non-empty line is code and there is nothing to scan for
+        if self.lang_config.line_comment.is_empty() && self.lang_config.block_comment_start.is_empty() {
+            return if line.trim().is_empty() { LineType::Blank } else { LineType::Code };
+        }
+        
+        let mut remaining = line;
+        let mut has_code = false;
+        
+        loop {
+            if state.in_comment() {
+                if let Some(end_pos) = remaining.find(state.end.as_str()) {
+                    remaining = &remaining[end_pos + state.end.len()..];
+                    state.close();
+                    continue;
+                } else {
+                    return if has_code { LineType::Code } else { LineType::Comment };
+                }
+            }
+            
+            // Check for start of block comment
+            let mut block_start_pos = None;
+            let mut block_start_len = 0;
+            let mut matching_end = String::new();
+            
+            for (i, start) in self.lang_config.block_comment_start.iter().enumerate() {
+                // A start without a usable end could never close; ignore it
+                let Some(end) = self.lang_config.block_comment_end.get(i).filter(|end| !end.is_empty()) else {
+                    continue;
+                };
+                if let Some(pos) = self.find_token(remaining, start) {
+                    if block_start_pos.is_none() || pos < block_start_pos.unwrap() {
+                        block_start_pos = Some(pos);
+                        block_start_len = start.len();
+                        matching_end = end.clone();
+                    }
+                }
+            }
+            
+            // Check for line comment
+            let mut line_comment_pos = None;
+            for comment in &self.lang_config.line_comment {
+                if let Some(pos) = self.find_token(remaining, comment) {
+                    if line_comment_pos.is_none() || pos < line_comment_pos.unwrap() {
+                        line_comment_pos = Some(pos);
+                    }
+                }
+            }
+            
+            // Determine what comes first
+            match (block_start_pos, line_comment_pos) {
+                (Some(block_pos), Some(line_pos)) if block_pos <= line_pos => {
+                    // Block comment starts first
+                    if block_pos > 0 && !remaining[..block_pos].trim().is_empty() {
+                        has_code = true;
+                    }
+                    remaining = &remaining[block_pos + block_start_len..];
+                    state.open(matching_end);
+                }
+                (Some(block_pos), None) => {
+                    // Only block comment
+                    if block_pos > 0 && !remaining[..block_pos].trim().is_empty() {
+                        has_code = true;
+                    }
+                    remaining = &remaining[block_pos + block_start_len..];
+                    state.open(matching_end);
+                }
+                (_, Some(line_pos)) => {
+                    // Line comment (possibly after block comment check)
+                    if line_pos > 0 && !remaining[..line_pos].trim().is_empty() {
+                        has_code = true;
+                    }
+                    return if has_code { LineType::Code } else { LineType::Comment };
+                }
+                (None, None) => {
+                    // No comments found
+                    if !remaining.trim().is_empty() {
+                        has_code = true;
+                    }
+                    break;
+                }
+            }
+        }
+        
+        // Without code, a non-empty line was consumed entirely by comments,
+        // e.g. a block comment that opens and closes on the same line
+        if has_code {
+            LineType::Code
+        } else if line.trim().is_empty() {
+            LineType::Blank
+        } else {
+            LineType::Comment
+        }
+    }
+}
+
+// Offset of `close` in `text`, skipping backslash-escaped characters
+fn find_string_end(text: &str, close: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        if text[pos..].starts_with(close) {
+            return Some(pos);
+        }
+        if c == '\\' {
+            chars.next();
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FunctionStyle {
+    // Brace-delimited body introduced by a keyword (`fn`, `func`, `function`)
+    Keyword(&'static str),
+    // Brace-delimited body following `name(...)`, as in C, Java or C#
+    CLike,
+    // `def name(...):` followed by an indented body
+    Indent,
+}
+
+fn function_style(lang_name: &str) -> Option<FunctionStyle> {
+    match lang_name {
+        "Rust" => Some(FunctionStyle::Keyword("fn")),
+        "Go" => Some(FunctionStyle::Keyword("func")),
+        "PHP" => Some(FunctionStyle::Keyword("function")),
+        "C/C++" | "Java" | "C#" | "JavaScript" | "TypeScript" => Some(FunctionStyle::CLike),
+        "Python" => Some(FunctionStyle::Indent),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct FunctionSpan {
+    start_line: usize,
+    end_line: usize,
+    body: String,
+}
+
+impl FunctionSpan {
+    fn line_count(&self) -> usize {
+        self.end_line - self.start_line + 1
+    }
+}
+
+#[derive(Debug)]
+struct LongFunction {
+    path: PathBuf,
+    start_line: usize,
+    lines: usize,
+}
+
+const CONTROL_KEYWORDS: [&str; 12] = [
+    "if", "else", "for", "foreach", "while", "switch", "catch", "do",
+    "return", "using", "lock", "synchronized",
+];
+
+fn is_identifier(word: &str) -> bool {
+    !word.is_empty()
+        && word.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+        && !word.chars().next().unwrap().is_ascii_digit()
+}
+
+fn is_function_header(line: &str, style: FunctionStyle) -> bool {
+    match style {
+        FunctionStyle::Keyword(keyword) => line
+            .split(|c: char| c.is_whitespace() || c == '(')
+            .any(|word| word == keyword)
+            && line.contains('('),
+        FunctionStyle::CLike => {
+            let Some(paren) = line.find('(') else { return false };
+            let before = line[..paren].trim_end();
+            let Some(name) = before.rsplit(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$')).next() else {
+                return false;
+            };
+            let first_word = line.split(|c: char| !(c.is_alphanumeric() || c == '_')).next().unwrap_or("");
+            is_identifier(name)
+                && !CONTROL_KEYWORDS.contains(&name)
+                && !CONTROL_KEYWORDS.contains(&first_word)
+                && !before.contains('=')
+                && !before.split_whitespace().any(|word| word == "new")
+                && !line.trim_end().ends_with(';')
+        }
+        FunctionStyle::Indent => {
+            let trimmed = line.trim_start();
+            trimmed.starts_with("def ") || trimmed.starts_with("async def ")
+        }
+    }
+}
+
+fn strip_line_comment<'a>(line: &'a str, lang_config: &LanguageConfig) -> &'a str {
+    lang_config.line_comment.iter()
+        .filter_map(|comment| line.find(comment.as_str()))
+        .min()
+        .map_or(line, |pos| &line[..pos])
+}
+
+// Heuristic function boundaries: brace depth for C-family languages and
+// indentation for Python. Braces inside strings are not accounted for.
+fn find_functions(lines: &[&str], lang_config: &LanguageConfig) -> Vec<FunctionSpan> {
+    let Some(style) = function_style(&lang_config.name) else {
+        return Vec::new();
+    };
+    
+    let mut functions = Vec::new();
+    for (start, line) in lines.iter().enumerate() {
+        let code = strip_line_comment(line, lang_config);
+        if !is_function_header(code, style) {
+            continue;
+        }
+        
+        let span = match style {
+            FunctionStyle::Indent => indented_body(lines, start, lang_config),
+            _ => braced_body(lines, start, lang_config),
+        };
+        functions.extend(span);
+    }
+    functions
+}
+
+fn braced_body(lines: &[&str], start: usize, lang_config: &LanguageConfig) -> Option<FunctionSpan> {
+    let mut depth = 0usize;
+    let mut opened = false;
+    let mut body = String::new();
+    
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        // Give up if the signature runs on without ever opening a body
+        if !opened && index > start + 5 {
+            return None;
+        }
+        
+        for c in strip_line_comment(line, lang_config).chars() {
+            match c {
+                ';' if !opened => return None,
+                '{' => {
+                    if opened {
+                        body.push(c);
+                    }
+                    opened = true;
+                    depth += 1;
+                }
+                '}' if opened => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(FunctionSpan { start_line: start + 1, end_line: index + 1, body });
+                    }
+                    body.push(c);
+                }
+                _ if opened => body.push(c),
+                _ => {}
+            }
+        }
+        if opened {
+            body.push('\n');
+        }
+    }
+    None
+}
+
+fn indented_body(lines: &[&str], start: usize, lang_config: &LanguageConfig) -> Option<FunctionSpan> {
+    let indent_of = |line: &str| line.len() - line.trim_start().len();
+    let header_indent = indent_of(lines[start]);
+    
+    // Find the line that closes the signature with `:`
+    let mut paren_depth = 0i32;
+    let mut header_end = None;
+    'signature: for (index, line) in lines.iter().enumerate().skip(start) {
+        for (pos, c) in strip_line_comment(line, lang_config).char_indices() {
+            match c {
+                '(' | '[' => paren_depth += 1,
+                ')' | ']' => paren_depth -= 1,
+                ':' if paren_depth == 0 => {
+                    header_end = Some((index, pos));
+                    break 'signature;
+                }
+                _ => {}
+            }
+        }
+    }
+    let (header_line, colon) = header_end?;
+    
+    // One-liner such as `def f(): pass`
+    let inline = lines[header_line][colon + 1..].trim();
+    if !inline.is_empty() && !inline.starts_with('#') {
+        return Some(FunctionSpan { start_line: start + 1, end_line: header_line + 1, body: inline.to_string() });
+    }
+    
+    let mut end_line = header_line;
+    let mut body = String::new();
+    for (index, line) in lines.iter().enumerate().skip(header_line + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indent_of(line) <= header_indent {
+            break;
+        }
+        end_line = index;
+        body.push_str(line.trim());
+        body.push('\n');
+    }
+    Some(FunctionSpan { start_line: start + 1, end_line: end_line + 1, body })
+}
+
+fn strip_block_comments(text: &str, lang_config: &LanguageConfig) -> String {
+    let mut result = text.to_string();
+    for (start, end) in lang_config.block_comment_start.iter().zip(&lang_config.block_comment_end) {
+        while let Some(open) = result.find(start.as_str()) {
+            let close = result[open + start.len()..].find(end.as_str())
+                .map_or(result.len(), |pos| open + start.len() + pos + end.len());
+            result.replace_range(open..close, "");
+        }
+    }
+    result
+}
+
+fn is_stub_statement(statement: &str) -> bool {
+    let lower = statement.to_lowercase().replace(' ', "");
+    statement == "pass"
+        || statement == "..."
+        || statement.starts_with("todo!")
+        || statement.starts_with("unimplemented!")
+        || ((lower.starts_with("throw") || lower.starts_with("raise") || lower.starts_with("panic("))
+            && ["notimplemented", "unsupportedoperation", "todo"].iter().any(|marker| lower.contains(marker)))
+}
+
+// A stub body is empty, holds only comments, or only placeholders such as
+// `todo!()`, `unimplemented!()`, `pass` or `throw new NotImplementedException()`.
+fn is_stub_body(body: &str, lang_config: &LanguageConfig) -> bool {
+    let body = strip_block_comments(body, lang_config);
+    let mut in_docstring = false;
+    let mut statements = Vec::new();
+    
+    for line in body.lines() {
+        let line = strip_line_comment(line, lang_config).trim();
+        // Python docstrings document a stub, they don't implement it
+        if lang_config.name == "Python" && (in_docstring || line.starts_with("\"\"\"") || line.starts_with("'''")) {
+            let quotes = line.matches("\"\"\"").count() + line.matches("'''").count();
+            if quotes % 2 == 1 {
+                in_docstring = !in_docstring;
+            }
+            continue;
+        }
+        statements.extend(line.split(';').map(str::trim).filter(|statement| !statement.is_empty()));
+    }
+    
+    statements.iter().all(|statement| is_stub_statement(statement))
+}
+
+// First quoted string in `text`, e.g. the module in `from 'react'`
+fn quoted(text: &str) -> Option<&str> {
+    let start = text.find(['"', '\'', '`'])?;
+    let quote = text[start..].chars().next()?;
+    let rest = &text[start + 1..];
+    rest.find(quote).map(|end| &rest[..end])
+}
+
+// Module names imported by one line, using each language's import syntax
+fn line_imports(line: &str, lang_name: &str, in_go_block: &mut bool) -> Vec<String> {
+    let word_after = |prefix: &str| line.strip_prefix(prefix).map(str::trim);
+    match lang_name {
+        "Rust" => {
+            let rest = line.trim_start_matches("pub ");
+            let rest = rest.strip_prefix("pub(crate) ").unwrap_or(rest);
+            if let Some(path) = rest.strip_prefix("use ") {
+                let path = path.split(['{', ';', ' ']).next().unwrap_or("");
+                vec![path.trim_end_matches("::*").trim_end_matches("::").to_string()]
+            } else if let Some(name) = rest.strip_prefix("extern crate ") {
+                vec![name.split([';', ' ']).next().unwrap_or("").to_string()]
+            } else {
+                Vec::new()
+            }
+        }
+        "Python" => {
+            if let Some(names) = word_after("import ") {
+                names.split(',')
+                    .map(|name| name.split_whitespace().next().unwrap_or("").to_string())
+                    .collect()
+            } else if let Some(rest) = word_after("from ") {
+                vec![rest.split_whitespace().next().unwrap_or("").to_string()]
+            } else {
+                Vec::new()
+            }
+        }
+        "JavaScript" | "TypeScript" => {
+            if let Some(pos) = line.find(" from ") {
+                quoted(&line[pos..]).map(str::to_string).into_iter().collect()
+            } else if line.starts_with("import ") || line.contains("require(") || line.contains("import(") {
+                let start = line.find("require(").or_else(|| line.find("import")).unwrap_or(0);
+                quoted(&line[start..]).map(str::to_string).into_iter().collect()
+            } else {
+                Vec::new()
+            }
+        }
+        "Go" => {
+            if *in_go_block {
+                if line.starts_with(')') {
+                    *in_go_block = false;
+                }
+                quoted(line).map(str::to_string).into_iter().collect()
+            } else if line.starts_with("import (") {
+                *in_go_block = true;
+                Vec::new()
+            } else if line.starts_with("import ") {
+                quoted(line).map(str::to_string).into_iter().collect()
+            } else {
+                Vec::new()
+            }
+        }
+        "Java" | "C#" => {
+            let rest = word_after("import ").or_else(|| word_after("using ").filter(|rest| !rest.contains('(')));
+            match rest.and_then(|rest| rest.strip_suffix(';')) {
+                Some(name) => {
+                    let name = name.trim_start_matches("static ");
+                    // `using Alias = Namespace.Type;` imports the right-hand side
+                    vec![name.rsplit('=').next().unwrap_or(name).trim().to_string()]
+                }
+                None => Vec::new(),
+            }
+        }
+        "C/C++" => match word_after("#include") {
+            Some(header) => header.trim_start_matches('<').split(['>', '"']).find(|name| !name.is_empty())
+                .map(str::to_string).into_iter().collect(),
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn extract_imports(content: &str, lang_config: &LanguageConfig) -> Vec<String> {
+    let mut imports = std::collections::BTreeSet::new();
+    let mut in_go_block = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if lang_config.line_comment.iter().any(|comment| line.starts_with(comment.as_str())) && !line.starts_with("#include") {
+            continue;
+        }
+        imports.extend(line_imports(line, &lang_config.name, &mut in_go_block).into_iter().filter(|name| !name.is_empty()));
+    }
+    imports.into_iter().collect()
+}
+
+// Hashes of a file's non-blank lines with their 1-based line numbers.
+// Whitespace is normalized so reindented copies still match.
+fn line_hashes(content: &str) -> Vec<(usize, u64)> {
+    use std::hash::{Hash, Hasher};
+    
+    content.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            for word in line.split_whitespace() {
+                word.hash(&mut hasher);
+            }
+            (index + 1, hasher.finish())
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct DuplicateBlock {
+    // Non-blank lines in the block
+    lines: usize,
+    // Path and first and last line of every copy
+    copies: Vec<(PathBuf, usize, usize)>,
+}
+
+// Runs of at least `min_lines` identical non-blank lines that occur in more
+// than one place. Every window of `min_lines` lines is hashed; windows seen
+// more than once are duplicates, and consecutive duplicate windows shared by
+// the same set of copies are merged into one longer block. Copies in the
+// same file must not overlap, so a long run of one repeated line is not
+// reported as copies of itself.
+fn find_duplicate_blocks(files: &[(PathBuf, Vec<(usize, u64)>)], min_lines: usize) -> Vec<DuplicateBlock> {
+    use std::hash::{Hash, Hasher};
+    
+    let min_lines = min_lines.max(1);
+    let mut windows: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (file_index, (_, hashes)) in files.iter().enumerate() {
+        for (start, window) in hashes.windows(min_lines).enumerate() {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            for (_, hash) in window {
+                hash.hash(&mut hasher);
+            }
+            windows.entry(hasher.finish()).or_default().push((file_index, start));
+        }
+    }
+    for copies in windows.values_mut() {
+        // Copies are in file and line order
+        let mut last: Option<(usize, usize)> = None;
+        copies.retain(|&(file, start)| {
+            let overlaps = last.is_some_and(|(last_file, last_start)| last_file == file && start - last_start < min_lines);
+            if !overlaps {
+                last = Some((file, start));
+            }
+            !overlaps
+        });
+    }
+    windows.retain(|_, copies| copies.len() > 1);
+    
+    let mut window_of: HashMap<(usize, usize), u64> = HashMap::new();
+    for (key, copies) in &windows {
+        for copy in copies {
+            window_of.insert(*copy, *key);
+        }
+    }
+    // The single window shared by every copy after shifting by `offset`,
+    // if it has exactly those copies
+    let shifted = |copies: &[(usize, usize)], offset: isize| -> Option<u64> {
+        let mut keys = copies.iter().map(|&(file, start)| {
+            let start = start.checked_add_signed(offset)?;
+            window_of.get(&(file, start)).copied()
+        });
+        let key = keys.next()??;
+        let all_same = keys.all(|other| other == Some(key));
+        (all_same && windows[&key].len() == copies.len()).then_some(key)
+    };
+    
+    let mut blocks = Vec::new();
+    for copies in windows.values() {
+        // Only the first window of a run starts a block
+        if shifted(copies, -1).is_some() {
+            continue;
+        }
+        let mut extra = 0;
+        while shifted(copies, extra as isize + 1).is_some() {
+            extra += 1;
+        }
+        
+        let lines = min_lines + extra;
+        let mut located: Vec<(PathBuf, usize, usize)> = copies.iter()
+            .map(|&(file, start)| {
+                let (path, hashes) = &files[file];
+                (path.clone(), hashes[start].0, hashes[start + lines - 1].0)
+            })
+            .collect();
+        located.sort();
+        blocks.push(DuplicateBlock { lines, copies: located });
+    }
+    blocks.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.copies.cmp(&b.copies)));
+    blocks
+}
+
+// Buckets of the blame-based reports for lines without a commit
+const UNTRACKED: &str = "untracked";
+const UNCOMMITTED: &str = "uncommitted";
+
+// Calendar year of a Unix timestamp, using the proleptic Gregorian calendar
+fn year_of(timestamp: i64) -> i64 {
+    // Days since 0000-03-01, so leap days fall at the end of each year
+    let days = timestamp.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let year = year_of_era + era * 400;
+    // Months are counted from March, so January and February belong to the next year
+    if month_index >= 10 { year + 1 } else { year }
+}
+
+// "+0130" style offsets from git's committer-tz header, in seconds
+fn tz_offset_seconds(tz: &str) -> i64 {
+    let (sign, digits) = match tz.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, tz.trim_start_matches('+')),
+    };
+    let value: i64 = digits.parse().unwrap_or(0);
+    sign * ((value / 100) * 3600 + (value % 100) * 60)
+}
+
+// One line of `git blame --line-porcelain` output
+struct BlameLine {
+    author: String,
+    // Commit time shifted by the committer's time zone offset
+    local_time: i64,
+    // Changed in the working tree since the last commit
+    uncommitted: bool,
+    content: String,
+}
+
+// Blame for every line of `path`, or None when git can't blame it, such as
+// an untracked file or a file outside a repository
+fn git_blame(path: &Path) -> Option<Vec<BlameLine>> {
+    let (dir, name) = (path.parent()?, path.file_name()?);
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let output = std::process::Command::new("git")
+        .args(["blame", "--line-porcelain", "--"])
+        .arg(name)
+        .current_dir(dir)
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    
+    // Every line of --line-porcelain output is a commit header followed by
+    // the line's content prefixed with a tab
+    let mut lines = Vec::new();
+    let mut author = String::new();
+    let mut uncommitted = false;
+    let mut time = 0i64;
+    let mut tz = 0i64;
+    for entry in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(content) = entry.strip_prefix('\t') {
+            lines.push(BlameLine {
+                author: author.clone(),
+                local_time: time + tz,
+                uncommitted,
+                content: content.to_string(),
+            });
+        } else if let Some(value) = entry.strip_prefix("author ") {
+            author = value.to_string();
+        } else if let Some(value) = entry.strip_prefix("committer-time ") {
+            time = value.parse().unwrap_or(0);
+        } else if let Some(value) = entry.strip_prefix("committer-tz ") {
+            tz = tz_offset_seconds(value);
+        } else if entry.len() >= 40 && entry.as_bytes()[..40].iter().all(u8::is_ascii_hexdigit) {
+            uncommitted = entry[..40].bytes().all(|b| b == b'0');
+        }
+    }
+    Some(lines)
+}
+
+// Code lines per year of the commit that last modified them. Without
+// blame, all `code_lines` of the file go in UNTRACKED.
+fn blame_code_years(analyzer: &FileAnalyzer, blame: Option<&[BlameLine]>, code_lines: u64) -> HashMap<String, u64> {
+    let Some(blame) = blame else {
+        if code_lines == 0 {
+            return HashMap::new();
+        }
+        return HashMap::from([(UNTRACKED.to_string(), code_lines)]);
+    };
+    
+    let mut years = HashMap::new();
+    let mut block_state = BlockState::default();
+    for (index, line) in blame.iter().enumerate() {
+        if let LineType::Code = analyzer.classify_file_line(index, &line.content, &mut block_state) {
+            let year = if line.uncommitted { UNCOMMITTED.to_string() } else { year_of(line.local_time).to_string() };
+            *years.entry(year).or_default() += 1;
+        }
+    }
+    years
+}
+
+const TODO_MARKERS: [&str; 2] = ["TODO", "FIXME"];
+
+// True when `line` has a marker as a whole word, so TODOS or todo_list
+// don't count
+fn has_todo_marker(line: &str) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    TODO_MARKERS.iter().any(|marker| {
+        line.match_indices(marker).any(|(pos, _)| {
+            let before = line[..pos].chars().next_back();
+            let after = line[pos + marker.len()..].chars().next();
+            !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+        })
+    })
+}
+
+// Lines with a TODO marker in a comment per author of the line, counting
+// comments that trail code too. Lines that are not committed yet go in
+// UNCOMMITTED, and without blame every marker in the file goes in UNTRACKED.
+fn todo_authors(analyzer: &FileAnalyzer, blame: Option<&[BlameLine]>, path: &Path) -> HashMap<String, u64> {
+    let mut authors = HashMap::new();
+    let mut block_state = BlockState::default();
+    let lang_config = &analyzer.lang_config;
+    let mut count = |index: usize, line: &str, author: &str| {
+        let comment = match analyzer.classify_file_line(index, line, &mut block_state) {
+            LineType::Comment => Some(line),
+            // The comment part of a code line starts at its first comment
+            // token outside a string
+            LineType::Code => lang_config.line_comment.iter()
+                .chain(&lang_config.block_comment_start)
+                .filter_map(|token| analyzer.find_token(line, token))
+                .min()
+                .map(|pos| &line[pos..]),
+            LineType::Blank => None,
+        };
+        if comment.is_some_and(has_todo_marker) {
+            *authors.entry(author.to_string()).or_default() += 1;
+        }
+    };
+    
+    match blame {
+        Some(blame) => {
+            for (index, line) in blame.iter().enumerate() {
+                count(index, &line.content, if line.uncommitted { UNCOMMITTED } else { &line.author });
+            }
+        }
+        None => {
+            let content = std::fs::read_to_string(path).unwrap_or_default();
+            for (index, line) in content.lines().enumerate() {
+                count(index, line, UNTRACKED);
+            }
+        }
+    }
+    authors
+}
+
+// Destination of a --numstat path, which git writes as `old => new` or
+// `dir/{old => new}/file` for renames
+fn numstat_path(raw: &str) -> String {
+    if let (Some(open), Some(close)) = (raw.find('{'), raw.rfind('}')) {
+        if let Some((_, new)) = raw.get(open + 1..close).and_then(|renamed| renamed.split_once(" => ")) {
+            return format!("{}{}{}", &raw[..open], new, &raw[close + 1..]).replace("//", "/");
+        }
+    }
+    match raw.split_once(" => ") {
+        Some((_, new)) => new.to_string(),
+        None => raw.to_string(),
+    }
+}
+
+// Directory to run git in and the pathspec that limits it to `path`
+fn git_scope(path: &Path) -> (&Path, &std::ffi::OsStr) {
+    if path.is_dir() {
+        (path, std::ffi::OsStr::new("."))
+    } else {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        (dir, path.file_name().unwrap_or(path.as_os_str()))
+    }
+}
+
+// Lines added and removed per language over the commits in `range`, from
+// `git log --numstat` limited to `path`. Files are mapped to languages by
+// extension, so deleted files count too; binary changes are skipped.
+fn git_churn(path: &Path, range: &str, lang_db: &LanguageDatabase) -> std::result::Result<HashMap<String, (u64, u64)>, String> {
+    let (dir, pathspec) = git_scope(path);
+    let output = std::process::Command::new("git")
+        .args(["log", "--numstat", "--format=", "--end-of-options", range, "--"])
+        .arg(pathspec)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    
+    let mut churn: HashMap<String, (u64, u64)> = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(added), Some(removed), Some(file)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let (Ok(added), Ok(removed)) = (added.parse::<u64>(), removed.parse::<u64>()) else {
+            continue;
+        };
+        if let Some(lang) = lang_db.get_language(Path::new(&numstat_path(file))) {
+            let entry = churn.entry(lang.name.clone()).or_default();
+            entry.0 += added;
+            entry.1 += removed;
+        }
+    }
+    Ok(churn)
+}
+
+// How a file changed, for --comment-only-changes. Ordered from most to
+// least worth reviewing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ChangeKind {
+    Code,
+    CommentOnly,
+    WhitespaceOnly,
+}
+
+impl ChangeKind {
+    fn label(self) -> &'static str {
+        match self {
+            ChangeKind::Code => "code-changed",
+            ChangeKind::CommentOnly => "comment-only-changed",
+            ChangeKind::WhitespaceOnly => "whitespace-only-changed",
+        }
+    }
+}
+
+// One file of a full-context `git diff`: every line of the old and the new
+// version, flagged when the line was removed or added
+#[derive(Debug, Default)]
+struct FileChange {
+    path: String,
+    old: Vec<(String, bool)>,
+    new: Vec<(String, bool)>,
+}
+
+fn parse_full_diff(diff: &str) -> Vec<FileChange> {
+    let mut files: Vec<FileChange> = Vec::new();
+    // Header lines such as `--- a/x` come before the first hunk; inside a
+    // hunk the same text is a removed `-- x` line
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            files.push(FileChange::default());
+            in_hunk = false;
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+        
+        if !in_hunk {
+            // git ends the path with a tab when it contains a space
+            if line.starts_with("@@") {
+                in_hunk = true;
+            } else if let Some(old_path) = line.strip_prefix("--- a/") {
+                file.path = old_path.trim_end_matches('\t').to_string();
+            } else if let Some(new_path) = line.strip_prefix("+++ b/") {
+                file.path = new_path.trim_end_matches('\t').to_string();
+            }
+            continue;
+        }
+        match line.split_at_checked(1) {
+            Some((" ", content)) => {
+                file.old.push((content.to_string(), false));
+                file.new.push((content.to_string(), false));
+            }
+            Some(("-", content)) => file.old.push((content.to_string(), true)),
+            Some(("+", content)) => file.new.push((content.to_string(), true)),
+            // "\ No newline at end of file"
+            _ => {}
+        }
+    }
+    files
+}
+
+// A change that leaves every line's code, ignoring whitespace and line
+// comments, as it was is comment-only; one that leaves all text apart from
+// whitespace as it was is whitespace-only. Lines are classified with their
+// whole file, so lines inside block comments are recognized.
+fn classify_change(analyzer: &FileAnalyzer, change: &FileChange) -> ChangeKind {
+    let changed_parts = |lines: &[(String, bool)]| {
+        let mut block_state = BlockState::default();
+        let mut code = Vec::new();
+        let mut text = String::new();
+        for (index, (line, changed)) in lines.iter().enumerate() {
+            let line_type = analyzer.classify_file_line(index, line, &mut block_state);
+            if !changed {
+                continue;
+            }
+            text.extend(line.chars().filter(|c| !c.is_whitespace()));
+            if let LineType::Code = line_type {
+                let end = analyzer.lang_config.line_comment.iter()
+                    .filter_map(|comment| analyzer.find_token(line, comment))
+                    .min()
+                    .unwrap_or(line.len());
+                code.push(line[..end].chars().filter(|c| !c.is_whitespace()).collect::<String>());
+            }
+        }
+        code.retain(|part| !part.is_empty());
+        (code, text)
+    };
+    
+    let (old_code, old_text) = changed_parts(&change.old);
+    let (new_code, new_text) = changed_parts(&change.new);
+    if old_text == new_text {
+        ChangeKind::WhitespaceOnly
+    } else if old_code == new_code {
+        ChangeKind::CommentOnly
+    } else {
+        ChangeKind::Code
+    }
+}
+
+// Classifies every changed file of a recognized language in `git diff
+// <range>`, sorted by kind and then path. A single revision compares it
+// with the working tree, like `git diff` does.
+fn git_change_kinds(path: &Path, range: &str, lang_db: &LanguageDatabase) -> std::result::Result<Vec<(String, ChangeKind)>, String> {
+    let (dir, pathspec) = git_scope(path);
+    // Enough context for each file to come out as one hunk, so classify_change
+    // sees complete files. The prefixes parse_full_diff expects are fixed, in
+    // case diff.noprefix or diff.mnemonicPrefix is set.
+    let output = std::process::Command::new("git")
+        .args(["-c", "core.quotepath=off", "diff", "--no-color", "--no-ext-diff", "--unified=1000000"])
+        .args(["--src-prefix=a/", "--dst-prefix=b/", "--end-of-options", range, "--"])
+        .arg(pathspec)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    
+    let mut changes: Vec<(String, ChangeKind)> = parse_full_diff(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        // Binary files and mode-only changes have no changed lines
+        .filter(|change| change.old.iter().chain(&change.new).any(|(_, changed)| *changed))
+        .filter_map(|change| {
+            let lang_config = lang_db.get_language(Path::new(&change.path))?;
+            let analyzer = FileAnalyzer::new(Arc::clone(lang_config));
+            let kind = classify_change(&analyzer, &change);
+            Some((change.path, kind))
+        })
+        .collect();
+    changes.sort_by(|(a_path, a_kind), (b_path, b_kind)| a_kind.cmp(b_kind).then_with(|| a_path.cmp(b_path)));
+    Ok(changes)
+}
+
+fn print_change_kinds(changes: &[(String, ChangeKind)], range: &str) {
+    println!("Changes in {}", range);
+    println!("{:<60} {:>24}", "File", "Change");
+    println!("{}", "-".repeat(85));
+    for (path, kind) in changes {
+        println!("{:<60} {:>24}", path, kind.label());
+    }
+    println!("{}", "-".repeat(85));
+    let counts: Vec<String> = [ChangeKind::Code, ChangeKind::CommentOnly, ChangeKind::WhitespaceOnly].iter()
+        .map(|kind| format!("{} {}", changes.iter().filter(|(_, k)| k == kind).count(), kind.label()))
+        .collect();
+    println!("{}", counts.join(", "));
+}
+
+// Languages sorted by total churn, most volatile first
+fn print_churn(churn: &HashMap<String, (u64, u64)>, range: &str, sum_label: &str) {
+    let mut sorted_results: Vec<_> = churn.iter().collect();
+    sorted_results.sort_by_key(|(lang, (added, removed))| (std::cmp::Reverse(added + removed), lang.as_str()));
+    
+    println!("Churn over {}", range);
+    println!("{:<20} {:>10} {:>10} {:>10}", "Language", "Added", "Removed", "Churn");
+    println!("{}", "-".repeat(53));
+    let (mut total_added, mut total_removed) = (0, 0);
+    for (lang, (added, removed)) in sorted_results {
+        println!("{:<20} {:>10} {:>10} {:>10}", lang, added, removed, added + removed);
+        total_added += added;
+        total_removed += removed;
+    }
+    println!("{}", "-".repeat(53));
+    println!("{:<20} {:>10} {:>10} {:>10}", sum_label, total_added, total_removed, total_added + total_removed);
+}
+
+// Common build/cache directories skipped unless --no-default-excludes is given
+const DEFAULT_SKIP_DIRS: [&str; 19] = [
+    "target", "node_modules", ".git", ".svn", ".hg", 
+    "build", "dist", "out", "bin", "obj", ".vs", ".vscode",
+    "__pycache__", ".pytest_cache", ".mypy_cache",
+    "vendor", "deps", ".idea", ".gradle"
+];
+
+fn should_skip_path(path: &Path, options: &ScanOptions) -> bool {
+    for component in path.components() {
+        let component_str = component.as_os_str().to_string_lossy().to_lowercase();
+        let default_skip = !options.no_default_excludes
+            && DEFAULT_SKIP_DIRS.contains(&component_str.as_str())
+            && !options.include_dirs.contains(&component_str);
+        if default_skip || options.exclude_dirs.contains(&component_str) {
+            return true;
+        }
+    }
+    
+    // Skip hidden files and directories (starting with .)
+    if let Some(filename) = path.file_name() {
+        let filename_str = filename.to_string_lossy();
+        if filename_str.starts_with('.') && filename_str.len() > 1 {
+            return true;
+        }
+    }
+    
+    false
+}
+
+// Windows extended-length paths such as \\?\C:\src or \\?\UNC\host\share
+// are turned back into regular paths. git and the --keep matcher can't
+// handle the prefix, and std already switches to the extended-length form
+// by itself whenever a path exceeds MAX_PATH, so deep trees still open.
+#[cfg(windows)]
+fn strip_verbatim_prefix(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = path.strip_prefix(r"\\?\").filter(|rest| rest.as_bytes().get(1) == Some(&b':')) {
+        rest.to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(not(windows))]
+fn strip_verbatim_prefix(path: &str) -> String {
+    path.to_string()
+}
+
+const FORMATS: [&str; 4] = ["text", "json", "json-array", "csv"];
+
+const PROJECT_CONFIG_NAME: &str = ".rcloc.toml";
+
+// Settings from a .rcloc.toml file, so a team can share them without
+// repeating flags. Each one stands in for the command-line option of the
+// same name when that option is not given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProjectConfig {
+    format: Option<String>,
+    exclude_dirs: Option<Vec<String>>,
+    include_dirs: Option<Vec<String>>,
+    count_long_methods: Option<usize>,
+    find_dupes: Option<usize>,
+    #[serde(default, rename = "language")]
+    languages: Vec<LanguageConfig>,
+    // The file the settings were read from
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl ProjectConfig {
+    fn load(path: &Path) -> std::result::Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut config: ProjectConfig = toml::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some(format) = config.format.as_deref().filter(|format| !FORMATS.contains(format)) {
+            return Err(format!("{}: unknown format {:?}, expected one of {}", path.display(), format, FORMATS.join(", ")));
+        }
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+}
+
+// The nearest .rcloc.toml in the analyzed directory (the file's directory
+// when PATH is a file) or one of its parents
+fn find_project_config(root: &Path) -> Option<PathBuf> {
+    let dir = if root.is_dir() { root } else { root.parent()? };
+    let dir = std::fs::canonicalize(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }).ok()?;
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(PROJECT_CONFIG_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+// True when `id` was given on the command line rather than left at its
+// default, so it wins over .rcloc.toml
+fn given(matches: &clap::ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine)
+}
+
+fn output_format<'a>(matches: &'a clap::ArgMatches, project: &'a ProjectConfig) -> &'a str {
+    match &project.format {
+        Some(format) if !given(matches, "format") => format,
+        _ => matches.get_one::<String>("format").unwrap(),
+    }
+}
+
+// Destination of progress lines such as "Scanned N files...": stderr by
+// default, or the --progress-to file so stderr only carries diagnostics
+#[derive(Debug, Clone, Default)]
+struct Progress {
+    file: Option<Arc<Mutex<File>>>,
+    // Drops every message, for analyze_path
+    quiet: bool,
+}
+
+impl Progress {
+    fn quiet() -> Self {
+        Self { quiet: true, ..Default::default() }
+    }
+    
+    fn to_file(path: &Path) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { file: Some(Arc::new(Mutex::new(file))), quiet: false })
+    }
+    
+    // Progress is best effort; a failed write never stops the analysis
+    fn report(&self, message: &str) {
+        if self.quiet {
+            return;
+        }
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                let _ = writeln!(file, "{}", message);
+            }
+            None => eprintln!("{}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ScanOptions {
+    // Extra directory names to skip, lowercased like the defaults
+    exclude_dirs: Vec<String>,
+    // Default skip directories that should be analyzed after all
+    include_dirs: Vec<String>,
+    no_default_excludes: bool,
+    // Walk everything instead of honoring .gitignore/.ignore files
+    no_ignore: bool,
+    // Extra ignore file names with gitignore syntax, such as .dockerignore
+    ignore_files: Vec<String>,
+    // Gitignore-style patterns a file must match to be analyzed
+    keep: Option<Gitignore>,
+    // Set by the Ctrl-C handler to stop the walk early
+    cancelled: Arc<AtomicBool>,
+    progress: Progress,
+}
+
+fn parse_dir_list<'a>(dirs: impl Iterator<Item = &'a str>) -> Vec<String> {
+    dirs.map(|dir| dir.trim().to_lowercase())
+        .filter(|dir| !dir.is_empty())
+        .collect()
+}
+
+// Patterns are relative to `root`, or to its directory when it's a file
+fn build_keep_matcher(root: &Path, patterns: &[String]) -> std::result::Result<Gitignore, ignore::Error> {
+    let base = if root.is_dir() { root } else { root.parent().unwrap_or(Path::new("")) };
+    let mut builder = GitignoreBuilder::new(base);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    builder.build()
+}
+
+fn is_kept(path: &Path, options: &ScanOptions) -> bool {
+    match &options.keep {
+        Some(keep) => keep.matched_path_or_any_parents(path, false).is_ignore(),
+        None => true,
+    }
+}
+
+fn collect_files(path: &Path, lang_db: &LanguageDatabase, options: &ScanOptions) -> Vec<(PathBuf, Arc<LanguageConfig>)> {
+    let processed_files = Arc::new(AtomicU64::new(0));
+    let processed_files_clone = processed_files.clone();
+    
+    // Unless --no-ignore is given, .gitignore, .ignore and git's global and
+    // per-repo excludes apply, and hidden files and directories are skipped.
+    // The built-in skip list and any requested extra ignore files apply
+    // either way.
+    let mut walker = WalkBuilder::new(path);
+    walker
+        .standard_filters(!options.no_ignore)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    for name in &options.ignore_files {
+        walker.add_custom_ignore_filename(name);
+    }
+    let files: Vec<_> = walker.build()
+        .take_while(|_| !options.cancelled.load(Ordering::Relaxed))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .filter(|entry| !should_skip_path(entry.path(), options))
+        .filter(|entry| is_kept(entry.path(), options))
+        .filter_map(|entry| {
+            let count = processed_files_clone.fetch_add(1, Ordering::Relaxed);
+            if count.is_multiple_of(1000) {
+                options.progress.report(&format!("Scanned {} files...", count));
+            }
+            
+            let path = entry.path();
+            lang_db.get_language(path).map(|lang| (path.to_path_buf(), Arc::clone(lang)))
+        })
+        .collect();
+    
+    options.progress.report(&format!("Found {} files to analyze", files.len()));
+    files
+}
+
+fn apply_function_metrics(functions: &[FunctionSpan], path: &Path, lang_config: &LanguageConfig, options: &AnalysisOptions, stats: &mut FileStats) -> Vec<LongFunction> {
+    if options.count_stubs {
+        stats.stub_functions = functions.iter()
+            .filter(|function| is_stub_body(&function.body, lang_config))
+            .count() as u64;
+    }
+    
+    let Some(threshold) = options.long_function_threshold else {
+        return Vec::new();
+    };
+    functions.iter()
+        .filter(|function| function.line_count() > threshold)
+        .map(|function| LongFunction {
+            path: path.to_path_buf(),
+            start_line: function.start_line,
+            lines: function.line_count(),
+        })
+        .collect()
+}
+
+// Analyze a buffer piped on stdin (`input`), detecting its language from
+// the name the caller supplies (e.g. the editor's buffer path)
+fn analyze_stdin<R: Read>(mut input: R, filename: &Path, lang_config: Arc<LanguageConfig>, options: &AnalysisOptions) -> Result<AnalysisResult> {
+    let mut content = String::new();
+    input.read_to_string(&mut content)?;
+    
+    let analyzer = FileAnalyzer::new(lang_config);
+    let mut stats = analyzer.analyze_reader(content.as_bytes())?;
+    let mut result = AnalysisResult::default();
+    if options.skip_empty && stats.code_lines == 0 && stats.comment_lines == 0 {
+        return Ok(result);
+    }
+    
+    let mut extras = FileExtras::default();
+    if options.count_stubs || options.long_function_threshold.is_some() {
+        let functions = analyzer.find_functions_in(&content);
+        extras.long_functions = apply_function_metrics(&functions, filename, &analyzer.lang_config, options, &mut stats);
+    }
+    if options.deps_graph {
+        extras.imports = extract_imports(&content, &analyzer.lang_config);
+    }
+    if options.duplicate_block_lines.is_some() {
+        extras.line_hashes = line_hashes(&content);
+    }
+    let file = FileResult {
+        path: filename.to_path_buf(),
+        language: analyzer.lang_config.name.clone(),
+        stats,
+    };
+    result.add_file(file, extras, options);
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Default)]
+struct AnalysisOptions {
+    skip_empty: bool,
+    count_stubs: bool,
+    long_function_threshold: Option<usize>,
+    directory_tree: bool,
+    by_file: bool,
+    deps_graph: bool,
+    by_year: bool,
+    todos_by_author: bool,
+    by_extension: bool,
+    // Minimum block length for --find-dupes
+    duplicate_block_lines: Option<usize>,
+    // Receives every file as it is analyzed, for --format json-array
+    json_stream: Option<Arc<JsonArrayStream>>,
+    // Set by the Ctrl-C handler; remaining files are skipped once it is true
+    cancelled: Arc<AtomicBool>,
+    progress: Progress,
+}
+
+// Files analyzed in parallel between writes of --format json-array
+const STREAM_CHUNK_FILES: usize = 1024;
+
+fn analyze_files(files: Vec<(PathBuf, Arc<LanguageConfig>)>, options: &AnalysisOptions) -> AnalysisResult {
+    let processed = Arc::new(AtomicU64::new(0));
+    let total = files.len() as u64;
+    let binary_files = AtomicU64::new(0);
+    let unreadable_files = AtomicU64::new(0);
+    
+    let analyze_one = |(path, lang_config): (PathBuf, Arc<LanguageConfig>)| {
+        if options.cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        
+        let count = processed.fetch_add(1, Ordering::Relaxed);
+        if count.is_multiple_of(100) {
+            options.progress.report(&format!("Analyzed {}/{} files ({:.1}%)", count, total, (count as f64 / total as f64) * 100.0));
+        }
+        
+        // Cloning the Arc is a refcount bump, not a copy of the comment tables
+        let analyzer = FileAnalyzer::new(Arc::clone(&lang_config));
+        match analyzer.analyze_file(&path) {
+            Ok(FileOutcome::Binary) => {
+                binary_files.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Ok(FileOutcome::Counted(stats)) if options.skip_empty && stats.code_lines == 0 && stats.comment_lines == 0 => None,
+            Ok(FileOutcome::Counted(mut stats)) => {
+                let mut extras = FileExtras::default();
+                if options.count_stubs || options.long_function_threshold.is_some() {
+                    let functions = analyzer.find_functions(&path).unwrap_or_default();
+                    extras.long_functions = apply_function_metrics(&functions, &path, &lang_config, options, &mut stats);
+                }
+                if options.deps_graph || options.duplicate_block_lines.is_some() {
+                    let content = std::fs::read_to_string(&path).unwrap_or_default();
+                    if options.deps_graph {
+                        extras.imports = extract_imports(&content, &lang_config);
+                    }
+                    if options.duplicate_block_lines.is_some() {
+                        extras.line_hashes = line_hashes(&content);
+                    }
+                }
+                if options.by_year || options.todos_by_author {
+                    let blame = git_blame(&path);
+                    if options.by_year {
+                        extras.years = blame_code_years(&analyzer, blame.as_deref(), stats.code_lines);
+                    }
+                    if options.todos_by_author {
+                        extras.todo_authors = todo_authors(&analyzer, blame.as_deref(), &path);
+                    }
+                }
+                let file = FileResult { path, language: lang_config.name.clone(), stats };
+                Some((file, extras))
+            }
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                unreadable_files.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    };
+    
+    let mut result = if options.json_stream.is_some() {
+        // Streamed files must come out in input order, so chunks are
+        // analyzed in parallel and added in order. Only one chunk of
+        // per-file results is held at a time.
+        let mut result = AnalysisResult::default();
+        let mut files = files.into_iter();
+        loop {
+            let chunk: Vec<_> = files.by_ref().take(STREAM_CHUNK_FILES).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            let analyzed: Vec<_> = chunk.into_par_iter().map(analyze_one).collect();
+            for (file, extras) in analyzed.into_iter().flatten() {
+                result.add_file(file, extras, options);
+            }
+        }
+        result
+    } else {
+        // Each rayon worker folds into its own result and the partial
+        // results are merged, so no intermediate Vec of per-file results
+        files
+            .into_par_iter()
+            .filter_map(analyze_one)
+            .fold(AnalysisResult::default, |mut result, (file, extras)| {
+                result.add_file(file, extras, options);
+                result
+            })
+            .reduce(AnalysisResult::default, AnalysisResult::merge)
+    };
+    result.binary_files = binary_files.into_inner();
+    result.unreadable_files = unreadable_files.into_inner();
+    result.interrupted = options.cancelled.load(Ordering::Relaxed);
+    result
+}
+
+// Counts `path`, a directory or a single file, like `rcloc PATH` with no
+// options: default exclusions and ignore files apply, and nothing is printed
+pub fn analyze_path(path: &Path, lang_db: &LanguageDatabase) -> AnalysisResult {
+    let scan_options = ScanOptions { progress: Progress::quiet(), ..Default::default() };
+    let options = AnalysisOptions { progress: Progress::quiet(), ..Default::default() };
+    analyze_files(collect_files(path, lang_db, &scan_options), &options)
+}
+
+const DENSITY_BUCKETS: [&str; 4] = ["0-10%", "10-25%", "25-50%", "50%+"];
+
+// Comment density is comment lines over comment plus code lines; files
+// without either have no density and fall in no bucket
+fn density_bucket(stats: &FileStats) -> Option<usize> {
+    let content_lines = stats.comment_lines + stats.code_lines;
+    if content_lines == 0 {
+        return None;
+    }
+    let density = stats.comment_lines as f64 / content_lines as f64;
+    Some(match density {
+        d if d < 0.10 => 0,
+        d if d < 0.25 => 1,
+        d if d < 0.50 => 2,
+        _ => 3,
+    })
+}
+
+#[derive(Debug)]
+struct FileResult {
+    path: PathBuf,
+    language: String,
+    stats: FileStats,
+}
+
+impl FileResult {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": portable_path(&self.path),
+            "language": self.language,
+            "blank_lines": self.stats.blank_lines,
+            "comment_lines": self.stats.comment_lines,
+            "code_lines": self.stats.code_lines,
+        })
+    }
+}
+
+// Writes one JSON array element by element, so per-file objects are
+// printed as they are produced instead of being held until the end. The
+// mutex keeps elements from different threads from interleaving.
+struct JsonArrayStream {
+    // Elements written so far and the destination, stdout outside tests
+    out: Mutex<(u64, Box<dyn Write + Send>)>,
+}
+
+impl std::fmt::Debug for JsonArrayStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonArrayStream").finish_non_exhaustive()
+    }
+}
+
+impl JsonArrayStream {
+    fn new(out: impl Write + Send + 'static) -> Self {
+        Self { out: Mutex::new((0, Box::new(out))) }
+    }
+    
+    fn write(&self, element: &serde_json::Value) -> Result<()> {
+        let mut guard = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let (written, out) = &mut *guard;
+        let separator = if *written == 0 { "[\n" } else { ",\n" };
+        write!(out, "{}{}", separator, element)?;
+        *written += 1;
+        Ok(())
+    }
+    
+    // Appends the last element and closes the array, so even a run without
+    // files prints a well-formed array
+    fn finish(&self, last: &serde_json::Value) -> Result<()> {
+        self.write(last)?;
+        let mut guard = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(guard.1, "\n]")?;
+        guard.1.flush()
+    }
+}
+
+// Per-file data for the optional reports; each field stays empty unless
+// its report was requested
+#[derive(Debug, Default)]
+struct FileExtras {
+    long_functions: Vec<LongFunction>,
+    imports: Vec<String>,
+    years: HashMap<String, u64>,
+    todo_authors: HashMap<String, u64>,
+    line_hashes: Vec<(usize, u64)>,
+}
+
+#[derive(Default)]
+pub struct AnalysisResult {
+    languages: HashMap<String, FileStats>,
+    // Only filled in with --by-file
+    files: Vec<FileResult>,
+    long_functions: Vec<LongFunction>,
+    // Per-language file counts for each DENSITY_BUCKETS entry
+    density: HashMap<String, [u64; 4]>,
+    // Code lines of every directory's subtree, keyed by directory path
+    directories: HashMap<PathBuf, u64>,
+    // Modules imported by each file, for --deps-graph
+    imports: Vec<(PathBuf, Vec<String>)>,
+    // Code lines per year of their last commit, for --by-year
+    years: HashMap<String, u64>,
+    // TODO/FIXME comment lines per blamed author, for --todos-by-author
+    todo_authors: HashMap<String, u64>,
+    // Non-blank line hashes of every file, for --find-dupes
+    line_hashes: Vec<(PathBuf, Vec<(usize, u64)>)>,
+    // Files left out of every count: binary data under a source extension,
+    // and files that failed to open or were not valid UTF-8
+    binary_files: u64,
+    unreadable_files: u64,
+    // Label of the totals row and JSON entry, from --sum-label
+    sum_label: Option<String>,
+    // Per-extension totals of each language, only filled in with --by-ext
+    extensions: HashMap<(String, String), FileStats>,
+    // Group the main table, JSON and CSV by extension instead of language
+    by_extension: bool,
+    duration: Duration,
+    // True when Ctrl-C stopped the run and the counts are partial
+    interrupted: bool,
+}
+
+impl AnalysisResult {
+    fn add_file(&mut self, file: FileResult, extras: FileExtras, options: &AnalysisOptions) {
+        if !extras.imports.is_empty() {
+            self.imports.push((file.path.clone(), extras.imports));
+        }
+        if !extras.line_hashes.is_empty() {
+            self.line_hashes.push((file.path.clone(), extras.line_hashes));
+        }
+        for (year, code_lines) in extras.years {
+            *self.years.entry(year).or_default() += code_lines;
+        }
+        for (author, todos) in extras.todo_authors {
+            *self.todo_authors.entry(author).or_default() += todos;
+        }
+        self.record_density(&file.language, &file.stats);
+        if options.directory_tree {
+            self.record_directories(&file.path, &file.stats);
+        }
+        *self.languages.entry(file.language.clone()).or_default() += file.stats.clone();
+        // Files without an extension, which only a --lang-manifest can
+        // assign a language, go in the "" group so the rows add up to the total
+        if options.by_extension {
+            let ext = file.path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+            *self.extensions.entry((file.language.clone(), ext)).or_default() += file.stats.clone();
+        }
+        self.long_functions.extend(extras.long_functions);
+        if let Some(stream) = &options.json_stream {
+            if let Err(e) = stream.write(&file.to_json()) {
+                eprintln!("Failed to write JSON: {}", e);
+            }
+        }
+        // Per-file rows are only kept when they will be printed
+        if options.by_file {
+            self.files.push(file);
+        }
+    }
+    
+    // Combines the partial results of two rayon workers. Lists are
+    // concatenated in arbitrary order; every report sorts before printing.
+    fn merge(mut self, other: Self) -> Self {
+        for (lang, stats) in other.languages {
+            *self.languages.entry(lang).or_default() += stats;
+        }
+        for (key, stats) in other.extensions {
+            *self.extensions.entry(key).or_default() += stats;
+        }
+        for (lang, buckets) in other.density {
+            let counts = self.density.entry(lang).or_default();
+            for (count, other_count) in counts.iter_mut().zip(buckets) {
+                *count += other_count;
+            }
+        }
+        for (dir, code_lines) in other.directories {
+            *self.directories.entry(dir).or_default() += code_lines;
+        }
+        for (year, code_lines) in other.years {
+            *self.years.entry(year).or_default() += code_lines;
+        }
+        for (author, todos) in other.todo_authors {
+            *self.todo_authors.entry(author).or_default() += todos;
+        }
+        self.files.extend(other.files);
+        self.long_functions.extend(other.long_functions);
+        self.imports.extend(other.imports);
+        self.line_hashes.extend(other.line_hashes);
+        self
+    }
+    
+    fn record_density(&mut self, lang: &str, stats: &FileStats) {
+        if let Some(bucket) = density_bucket(stats) {
+            self.density.entry(lang.to_string()).or_default()[bucket] += 1;
+        }
+    }
+    
+    fn record_directories(&mut self, path: &Path, stats: &FileStats) {
+        for dir in path.ancestors().skip(1) {
+            *self.directories.entry(dir.to_path_buf()).or_default() += stats.code_lines;
+        }
+    }
+    
+    fn sum_label(&self) -> &str {
+        self.sum_label.as_deref().unwrap_or("SUM")
+    }
+    
+    pub fn total(&self) -> FileStats {
+        self.languages.values().cloned().fold(FileStats::default(), |acc, stats| acc + stats)
+    }
+    
+    // Languages sorted descending by code lines, ties broken by name so
+    // repeated runs produce identical output
+    pub fn by_language(&self) -> Vec<(&String, &FileStats)> {
+        let mut sorted_results: Vec<_> = self.languages.iter().collect();
+        sorted_results.sort_by_key(|(lang, stats)| (std::cmp::Reverse(stats.code_lines), lang.as_str()));
+        sorted_results
+    }
+    
+    // Rows of the JSON and CSV output: languages, or extensions as written
+    // (without the dot) with --by-ext, in by_language order. An extension
+    // that files of several languages share is one row.
+    fn groups(&self) -> Vec<(String, FileStats)> {
+        if !self.by_extension {
+            return self.by_language().into_iter().map(|(lang, stats)| (lang.clone(), stats.clone())).collect();
+        }
+        let mut extensions: HashMap<&String, FileStats> = HashMap::new();
+        for ((_, ext), stats) in &self.extensions {
+            *extensions.entry(ext).or_default() += stats.clone();
+        }
+        let mut sorted_results: Vec<_> = extensions.into_iter().map(|(ext, stats)| (ext.clone(), stats)).collect();
+        sorted_results.sort_by(|(a_ext, a), (b_ext, b)| b.code_lines.cmp(&a.code_lines).then_with(|| a_ext.cmp(b_ext)));
+        sorted_results
+    }
+    
+    // Languages in by_language order, each with its extensions sorted like
+    // groups(), for the --by-ext table
+    fn extensions_by_language(&self) -> Vec<(&String, Vec<(&String, &FileStats)>)> {
+        self.by_language().into_iter()
+            .map(|(lang, _)| {
+                let mut extensions: Vec<_> = self.extensions.iter()
+                    .filter(|((ext_lang, _), _)| ext_lang == lang)
+                    .map(|((_, ext), stats)| (ext, stats))
+                    .collect();
+                extensions.sort_by_key(|(ext, stats)| (std::cmp::Reverse(stats.code_lines), ext.as_str()));
+                (lang, extensions)
+            })
+            .collect()
+    }
+    
+    fn group_header(&self) -> &str {
+        if self.by_extension { "Extension" } else { "Language" }
+    }
+    
+    // Files sorted descending by code lines, ties broken by path
+    fn by_file(&self) -> Vec<&FileResult> {
+        let mut sorted_files: Vec<_> = self.files.iter().collect();
+        sorted_files.sort_by(|a, b| b.stats.code_lines.cmp(&a.stats.code_lines).then_with(|| a.path.cmp(&b.path)));
+        sorted_files
+    }
+    
+    fn print_file_table(&self) {
+        println!("{:<50} {:<20} {:>10} {:>10} {:>10}", "File", "Language", "Blank", "Comment", "Code");
+        println!("{}", "-".repeat(104));
+        
+        for file in self.by_file() {
+            println!("{:<50} {:<20} {:>10} {:>10} {:>10}", 
+                     file.path.display(), file.language, file.stats.blank_lines, file.stats.comment_lines, file.stats.code_lines);
+        }
+        println!("{}", "-".repeat(104));
+    }
+    
+    pub fn print_table(&self) {
+        let row = |label: &str, stats: &FileStats| {
+            println!("{:<20} {:>10} {:>10} {:>10} {:>10}", 
+                     label, stats.files, stats.blank_lines, stats.comment_lines, stats.code_lines);
+        };
+        println!("{:<20} {:>10} {:>10} {:>10} {:>10}", "Language", "Files", "Blank", "Comment", "Code");
+        println!("{}", "-".repeat(70));
+        
+        if self.by_extension {
+            // Each language row is the subtotal of the extensions below it
+            for (lang, extensions) in self.extensions_by_language() {
+                row(lang, &self.languages[lang]);
+                for (ext, stats) in extensions {
+                    row(&format!("  {}", ext), stats);
+                }
+            }
+        } else {
+            for (lang, stats) in self.by_language() {
+                row(lang, stats);
+            }
+        }
+        
+        println!("{}", "-".repeat(70));
+        row(self.sum_label(), &self.total());
+    }
+    
+    // Object keyed by language (or extension) plus a totals entry named by
+    // sum_label()
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut object = serde_json::Map::new();
+        for (lang, stats) in self.groups() {
+            object.insert(lang, serde_json::json!(stats));
+        }
+        object.insert(self.sum_label().to_string(), serde_json::json!(self.total()));
+        serde_json::Value::Object(object)
+    }
+    
+    // Array of per-file objects in by_file order
+    fn files_to_json(&self) -> serde_json::Value {
+        self.by_file().into_iter().map(FileResult::to_json).collect()
+    }
+    
+    fn files_to_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "path,language,blank_lines,comment_lines,code_lines")?;
+        for file in self.by_file() {
+            writeln!(w, "{},{},{},{},{}", csv_field(&portable_path(&file.path)), csv_field(&file.language),
+                     file.stats.blank_lines, file.stats.comment_lines, file.stats.code_lines)?;
+        }
+        Ok(())
+    }
+    
+    // Header, one row per language in table order, then the SUM row
+    pub fn to_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "{},files,blank_lines,comment_lines,code_lines", self.group_header().to_lowercase())?;
+        let total_stats = self.total();
+        let groups = self.groups();
+        let rows = groups.iter()
+            .map(|(lang, stats)| (lang.as_str(), stats))
+            .chain(std::iter::once((self.sum_label(), &total_stats)));
+        for (lang, stats) in rows {
+            writeln!(w, "{},{},{},{},{}", csv_field(lang), stats.files, stats.blank_lines, stats.comment_lines, stats.code_lines)?;
+        }
+        Ok(())
+    }
+    
+    fn print_density_histogram(&self) {
+        let mut sorted_results: Vec<_> = self.density.iter().collect();
+        sorted_results.sort_by_key(|(lang, buckets)| (std::cmp::Reverse(buckets.iter().sum::<u64>()), lang.as_str()));
+        
+        println!("{:<20} {:>10} {:>10} {:>10} {:>10}", "Comment density", DENSITY_BUCKETS[0], DENSITY_BUCKETS[1], DENSITY_BUCKETS[2], DENSITY_BUCKETS[3]);
+        println!("{}", "-".repeat(64));
+        
+        let mut total = [0u64; 4];
+        for (lang, buckets) in sorted_results {
+            println!("{:<20} {:>10} {:>10} {:>10} {:>10}", lang, buckets[0], buckets[1], buckets[2], buckets[3]);
+            for (sum, count) in total.iter_mut().zip(buckets) {
+                *sum += count;
+            }
+        }
+        
+        println!("{}", "-".repeat(64));
+        println!("{:<20} {:>10} {:>10} {:>10} {:>10}", self.sum_label(), total[0], total[1], total[2], total[3]);
+    }
+    
+    // Code lines per year, oldest first, with uncommitted and untracked
+    // code at the end
+    fn print_years(&self) {
+        let mut years: Vec<_> = self.years.iter().collect();
+        years.sort_by_key(|(year, _)| match year.as_str() {
+            UNCOMMITTED => (1, ""),
+            UNTRACKED => (2, ""),
+            year => (0, year),
+        });
+        
+        println!("{:<20} {:>10} {:>10}", "Year", "Code", "Share");
+        println!("{}", "-".repeat(42));
+        let total: u64 = self.years.values().sum();
+        for (year, code_lines) in years {
+            let share = if total == 0 { 0.0 } else { *code_lines as f64 * 100.0 / total as f64 };
+            println!("{:<20} {:>10} {:>9.1}%", year, code_lines, share);
+        }
+        println!("{}", "-".repeat(42));
+        println!("{:<20} {:>10}", self.sum_label(), total);
+    }
+    
+    fn print_todo_authors(&self) {
+        let mut authors: Vec<_> = self.todo_authors.iter().collect();
+        authors.sort_by_key(|(author, todos)| (std::cmp::Reverse(**todos), author.as_str()));
+        
+        println!("{:<30} {:>10}", "Author", "TODOs");
+        println!("{}", "-".repeat(41));
+        for (author, todos) in authors {
+            println!("{:<30} {:>10}", author, todos);
+        }
+        println!("{}", "-".repeat(41));
+        println!("{:<30} {:>10}", self.sum_label(), self.todo_authors.values().sum::<u64>());
+    }
+    
+    // Directory hierarchy below `root`, like `du` for code lines. Children are
+    // sorted by size and chains of directories without code of their own
+    // are collapsed into a single `a/b/c` node.
+    fn print_directory_tree(&self, root: &Path) {
+        let mut children: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for dir in self.directories.keys() {
+            if let Some(parent) = dir.parent() {
+                children.entry(parent).or_default().push(dir);
+            }
+        }
+        for dirs in children.values_mut() {
+            dirs.sort_by_key(|dir| (std::cmp::Reverse(self.directories[*dir]), *dir));
+        }
+        
+        let root = if root.is_dir() { root } else { root.parent().unwrap_or(root) };
+        let Some(&root_total) = self.directories.get(root) else {
+            return;
+        };
+        
+        println!("{:>10}  Directory", "Code");
+        println!("{}", "-".repeat(70));
+        println!("{:>10}  {}", root_total, root.display());
+        self.print_tree_children(root, &children, "");
+    }
+    
+    fn print_tree_children(&self, dir: &Path, children: &HashMap<&Path, Vec<&Path>>, prefix: &str) {
+        let Some(dirs) = children.get(dir) else {
+            return;
+        };
+        
+        for (i, child) in dirs.iter().enumerate() {
+            let last = i + 1 == dirs.len();
+            let total = self.directories[*child];
+            
+            // Follow single-child chains that add no code of their own
+            let mut node = *child;
+            let mut name = child.file_name().unwrap_or_default().to_string_lossy().to_string();
+            while let Some([only]) = children.get(node).map(Vec::as_slice) {
+                if self.directories[*only] != total {
+                    break;
+                }
+                node = only;
+                name = format!("{}/{}", name, only.file_name().unwrap_or_default().to_string_lossy());
+            }
+            
+            let branch = if last { "└── " } else { "├── " };
+            println!("{:>10}  {}{}{}", total, prefix, branch, name);
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            self.print_tree_children(node, children, &child_prefix);
+        }
+    }
+    
+    // Graphviz DOT for .dot/.gv targets, otherwise JSON mapping each file
+    // to the modules it imports
+    fn write_deps_graph(&self, target: &Path) -> Result<()> {
+        let mut imports: Vec<_> = self.imports.iter().collect();
+        imports.sort_by(|a, b| a.0.cmp(&b.0));
+        
+        let mut out = std::io::BufWriter::new(File::create(target)?);
+        let extension = target.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if matches!(extension, "dot" | "gv") {
+            writeln!(out, "digraph deps {{")?;
+            writeln!(out, "    rankdir=LR;")?;
+            for (path, modules) in imports {
+                for module in modules {
+                    writeln!(out, "    {:?} -> {:?};", portable_path(path), module)?;
+                }
+            }
+            writeln!(out, "}}")?;
+        } else {
+            let mut graph = serde_json::Map::new();
+            for (path, modules) in imports {
+                graph.insert(portable_path(path), serde_json::json!(modules));
+            }
+            serde_json::to_writer_pretty(&mut out, &graph)?;
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+    
+    fn print_long_functions(&self, threshold: usize) {
+        let mut long_functions: Vec<_> = self.long_functions.iter().collect();
+        long_functions.sort_by(|a, b| b.lines.cmp(&a.lines)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.start_line.cmp(&b.start_line)));
+        
+        println!("Functions longer than {} lines: {}", threshold, long_functions.len());
+        if long_functions.is_empty() {
+            return;
+        }
+        println!("{:<60} {:>10}", "Location", "Lines");
+        println!("{}", "-".repeat(71));
+        for function in long_functions {
+            let location = format!("{}:{}", function.path.display(), function.start_line);
+            println!("{:<60} {:>10}", location, function.lines);
+        }
+    }
+    
+    fn print_duplicate_blocks(&self, min_lines: usize) {
+        let blocks = find_duplicate_blocks(&self.line_hashes, min_lines);
+        
+        println!("Duplicated blocks of {} or more lines: {}", min_lines, blocks.len());
+        for block in blocks {
+            println!("{}", "-".repeat(71));
+            println!("{} lines, {} copies", block.lines, block.copies.len());
+            for (path, start_line, end_line) in block.copies {
+                println!("    {}:{}-{}", path.display(), start_line, end_line);
+            }
+        }
+    }
+}
+
+// Per-language change from a baseline tree to the analyzed tree, for --diff
+struct Diff {
+    languages: Vec<(String, FileStatsDelta)>,
+    total: FileStatsDelta,
+    sum_label: String,
+}
+
+impl Diff {
+    // A language found on only one side shows its full counts, signed by
+    // the side it is on. Unchanged languages are left out. Rows are sorted
+    // by the size of the code change, ties broken by name.
+    fn between(current: &AnalysisResult, baseline: &AnalysisResult) -> Self {
+        let names: std::collections::BTreeSet<&String> = current.languages.keys()
+            .chain(baseline.languages.keys())
+            .collect();
+        let mut languages: Vec<(String, FileStatsDelta)> = names.into_iter()
+            .map(|lang| {
+                let now = current.languages.get(lang).cloned().unwrap_or_default();
+                let before = baseline.languages.get(lang).cloned().unwrap_or_default();
+                (lang.clone(), now - before)
+            })
+            .filter(|(_, delta)| *delta != FileStatsDelta::default())
+            .collect();
+        languages.sort_by(|(a_lang, a), (b_lang, b)| {
+            b.code_lines.unsigned_abs().cmp(&a.code_lines.unsigned_abs()).then_with(|| a_lang.cmp(b_lang))
+        });
+        Self {
+            languages,
+            total: current.total() - baseline.total(),
+            sum_label: current.sum_label().to_string(),
+        }
+    }
+    
+    fn print_table(&self) {
+        println!("{:<20} {:>10} {:>10} {:>10} {:>10}", "Language", "Files", "Blank", "Comment", "Code");
+        println!("{}", "-".repeat(70));
+        
+        for (lang, delta) in &self.languages {
+            println!("{:<20} {:>+10} {:>+10} {:>+10} {:>+10}",
+                     lang, delta.files, delta.blank_lines, delta.comment_lines, delta.code_lines);
+        }
+        
+        println!("{}", "-".repeat(70));
+        println!("{:<20} {:>+10} {:>+10} {:>+10} {:>+10}",
+                 self.sum_label, self.total.files, self.total.blank_lines, self.total.comment_lines, self.total.code_lines);
+    }
+    
+    // Same shape as AnalysisResult::to_json, with signed values
+    fn to_json(&self) -> serde_json::Value {
+        let mut object = serde_json::Map::new();
+        for (lang, delta) in &self.languages {
+            object.insert(lang.clone(), serde_json::json!(delta));
+        }
+        object.insert(self.sum_label.clone(), serde_json::json!(self.total));
+        serde_json::Value::Object(object)
+    }
+    
+    fn to_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "language,files,blank_lines,comment_lines,code_lines")?;
+        let rows = self.languages.iter()
+            .map(|(lang, delta)| (lang.as_str(), delta))
+            .chain(std::iter::once((self.sum_label.as_str(), &self.total)));
+        for (lang, delta) in rows {
+            writeln!(w, "{},{},{},{},{}", csv_field(lang), delta.files, delta.blank_lines, delta.comment_lines, delta.code_lines)?;
+        }
+        Ok(())
+    }
+}
+
+// Paths in JSON and CSV output and in the dependency graph always use `/`,
+// so output produced on Windows compares equal to output from elsewhere
+#[cfg(windows)]
+fn portable_path(path: &Path) -> String {
+    path.display().to_string().replace('\\', "/")
+}
+
+#[cfg(not(windows))]
+fn portable_path(path: &Path) -> String {
+    path.display().to_string()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn print_language_counts<'a>(results: impl Iterator<Item = (&'a String, &'a FileStats)>, title: &str, header: &str, sum_label: &str, count: impl Fn(&FileStats) -> u64) {
+    let mut sorted_results: Vec<_> = results.map(|(lang, stats)| (lang, count(stats))).collect();
+    sorted_results.sort_by_key(|(lang, count)| (std::cmp::Reverse(*count), lang.as_str()));
+    
+    println!("{:<20} {:>10}", title, header);
+    println!("{}", "-".repeat(31));
+    
+    let mut total = 0;
+    for (lang, count) in &sorted_results {
+        println!("{:<20} {:>10}", lang, count);
+        total += count;
+    }
+    
+    println!("{}", "-".repeat(31));
+    println!("{:<20} {:>10}", sum_label, total);
+}
+
+fn print_stub_functions(results: &HashMap<String, FileStats>, sum_label: &str) {
+    let languages = results.iter().filter(|(lang, _)| function_style(lang).is_some());
+    print_language_counts(languages, "Language", "Stubs", sum_label, |stats| stats.stub_functions);
+}
+
+fn print_comment_only_files(results: &HashMap<String, FileStats>, sum_label: &str) {
+    print_language_counts(results.iter(), "Comment-only files", "Files", sum_label, |stats| stats.comment_only_files);
+}
+
+// Closing stderr lines: files left out of the counts, interruption, timing
+fn print_run_notes(result: &AnalysisResult, progress: &Progress) {
+    if result.binary_files > 0 {
+        eprintln!("Skipped {} binary file(s)", result.binary_files);
+    }
+    if result.unreadable_files > 0 {
+        eprintln!("Skipped {} file(s) that could not be read", result.unreadable_files);
+    }
+    if result.interrupted {
+        eprintln!("Analysis was interrupted; results cover only the {} files analyzed before Ctrl-C", result.total().files);
+    }
+    progress.report(&format!("Analysis completed in {:.2} seconds", result.duration.as_secs_f64()));
+}
+
+// The supplementary reports only have a table layout
+fn warn_text_only_reports(matches: &clap::ArgMatches) {
+    let text_only = ["count-stubs", "count-long-methods", "comment-only-files", "comment-density", "tree", "by-year", "todos-by-author", "find-dupes", "churn", "comment-only-changes"];
+    for report in text_only {
+        if given(matches, report) {
+            eprintln!("Warning: --{} is only shown with --format text", report);
+        }
+    }
+}
+
+fn cli() -> Command {
+    Command::new("rcloc")
+        .version("1.0.0")
+        .about("A fast clone of cloc (Count Lines of Code) written in Rust")
+        .arg(
+            Arg::new("path")
+                .help("Directory or file to analyze")
+                .value_name("PATH")
+                .default_value(".")
+                .index(1)
+        )
+        .arg(
+            Arg::new("exclude-dirs")
+                .long("exclude-dirs")
+                .help("Exclude additional directories (comma-separated)")
+                .value_name("DIRS")
+        )
+        .arg(
+            Arg::new("include-dirs")
+                .long("include-dirs")
+                .help("Analyze directories that are skipped by default (comma-separated)")
+                .value_name("DIRS")
+        )
+        .arg(
+            Arg::new("no-default-excludes")
+                .long("no-default-excludes")
+                .help("Don't skip the built-in list of build and cache directories")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format")
+                .value_name("FORMAT")
+                .value_parser(FORMATS)
+                .default_value("text")
+        )
+        .arg(
+            Arg::new("by-file")
+                .long("by-file")
+                .help("Report every file separately, followed by the language totals")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("by-ext")
+                .long("by-ext")
+                .help("Group the totals by file extension instead of language")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("skip-empty")
+                .long("skip-empty")
+                .help("Exclude files with no code or comment lines (empty, whitespace-only or BOM-only)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("count-stubs")
+                .long("count-stubs")
+                .help("Count stub functions whose body is empty or only a placeholder (todo!(), pass, ...)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("count-long-methods")
+                .long("count-long-methods")
+                .help("Report functions longer than N lines (heuristic boundaries)")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("comment-only-files")
+                .long("comment-only-files")
+                .help("Show how many files per language contain comments but no code")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("comment-density")
+                .long("comment-density")
+                .help("Show how many files fall into each comment density bucket")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("tree")
+                .long("tree")
+                .help("Show the directory hierarchy with code lines per subtree")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("deps-graph")
+                .long("deps-graph")
+                .help("Write file -> imported module edges to FILE (DOT for .dot/.gv, JSON otherwise)")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("stdin")
+                .long("stdin")
+                .help("Read a single file's content from stdin instead of scanning PATH")
+                .action(ArgAction::SetTrue)
+                .requires("stdin-filename")
+        )
+        .arg(
+            Arg::new("stdin-filename")
+                .long("stdin-filename")
+                .help("File name used to detect the language of stdin content")
+                .value_name("FILE")
+                .requires("stdin")
+        )
+        .arg(
+            Arg::new("no-ignore")
+                .long("no-ignore")
+                .help("Don't respect .gitignore, .ignore or global git excludes")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("respect-dockerignore")
+                .long("respect-dockerignore")
+                .help("Also skip files matched by .dockerignore files")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("respect-npmignore")
+                .long("respect-npmignore")
+                .help("Also skip files matched by .npmignore files")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("keep")
+                .long("keep")
+                .help("Only analyze files matching this gitignore-style pattern (repeatable)")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("by-year")
+                .long("by-year")
+                .help("Show code lines per year of their last commit (runs git blame on every file)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("stdin")
+        )
+        .arg(
+            Arg::new("todos-by-author")
+                .long("todos-by-author")
+                .help("Show how many TODO/FIXME comments each author wrote (runs git blame on every file)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("stdin")
+        )
+        .arg(
+            Arg::new("find-dupes")
+                .long("find-dupes")
+                .help("Report blocks of N or more identical lines that appear in more than one place")
+                .value_name("N")
+                .num_args(0..=1)
+                .default_missing_value("10")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("churn")
+                .long("churn")
+                .help("Show lines added and removed per language over a git commit range, e.g. v1.0..HEAD")
+                .value_name("RANGE")
+                .conflicts_with("stdin")
+        )
+        .arg(
+            Arg::new("comment-only-changes")
+                .long("comment-only-changes")
+                .help("Classify each file changed in a git range as code, comment-only or whitespace-only changes")
+                .value_name("RANGE")
+                .conflicts_with("stdin")
+        )
+        .arg(
+            Arg::new("diff")
+                .long("diff")
+                .help("Show how line counts changed from OTHER_PATH to PATH, per language")
+                .value_name("OTHER_PATH")
+                .conflicts_with_all([
+                    "stdin", "by-file", "by-ext", "count-stubs", "count-long-methods", "comment-only-files",
+                    "comment-density", "tree", "by-year", "todos-by-author", "find-dupes", "churn", "comment-only-changes", "deps-graph",
+                ])
+        )
+        .arg(
+            Arg::new("sum-label")
+                .long("sum-label")
+                .help("Label of the totals row in tables and of the totals entry in JSON and CSV [default: SUM]")
+                .value_name("LABEL")
+        )
+        .arg(
+            Arg::new("progress-to")
+                .long("progress-to")
+                .help("Write progress messages to FILE instead of stderr (e.g. /dev/fd/3)")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Load additional language definitions from a TOML or JSON file")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("lang-manifest")
+                .long("lang-manifest")
+                .help("JSON object mapping file paths to language names, used instead of extension detection")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("list-languages")
+                .long("list-languages")
+                .help("Print every known language and its extensions, then exit")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("no-config")
+                .long("no-config")
+                .help("Don't load a .rcloc.toml file from PATH or its parent directories")
+                .action(ArgAction::SetTrue)
+        )
+}
+
+// The rcloc command line: parses the arguments, runs the analysis and
+// prints the report
+pub fn run() {
+    let matches = cli().get_matches();
+    
+    let path = &strip_verbatim_prefix(matches.get_one::<String>("path").unwrap());
+    let mut project = match find_project_config(Path::new(path)).filter(|_| !matches.get_flag("no-config")) {
+        Some(file) => ProjectConfig::load(&file).unwrap_or_else(|e| {
+            eprintln!("Invalid config file {}", e);
+            std::process::exit(1);
+        }),
+        None => ProjectConfig::default(),
+    };
+    
+    // Languages from .rcloc.toml come first so --config can override them
+    let mut lang_db = LanguageDatabase::new();
+    let project_languages = std::mem::take(&mut project.languages);
+    if let Err(e) = lang_db.add_language_file(&project.path, project_languages) {
+        eprintln!("Invalid config file {}", e);
+        std::process::exit(1);
+    }
+    if let Some(config) = matches.get_one::<String>("config") {
+        if let Err(e) = lang_db.load_config(Path::new(config)) {
+            eprintln!("Invalid --config file {}", e);
+            std::process::exit(1);
+        }
+    }
+    // Under --diff the manifest describes both trees
+    let diff_path = matches.get_one::<String>("diff").map(|other| strip_verbatim_prefix(other));
+    if let Some(manifest) = matches.get_one::<String>("lang-manifest") {
+        for root in std::iter::once(path).chain(&diff_path) {
+            if let Err(e) = lang_db.load_manifest(Path::new(manifest), Path::new(root)) {
+                eprintln!("Invalid --lang-manifest file {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if matches.get_flag("list-languages") {
+        lang_db.print_languages();
+        return;
+    }
+    let format = output_format(&matches, &project);
+    let progress = match matches.get_one::<String>("progress-to") {
+        Some(target) => match Progress::to_file(Path::new(target)) {
+            Ok(progress) => progress,
+            Err(e) => {
+                eprintln!("Failed to open --progress-to file {}: {}", target, e);
+                std::process::exit(1);
+            }
+        },
+        None => Progress::default(),
+    };
+    
+    // The first Ctrl-C stops the scan and prints what was counted so far;
+    // a second one exits immediately
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let handler_cancelled = Arc::clone(&cancelled);
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_cancelled.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("Interrupted, printing partial results...");
+    }) {
+        eprintln!("Failed to install Ctrl-C handler: {}", e);
+    }
+    
+    let options = AnalysisOptions {
+        skip_empty: matches.get_flag("skip-empty"),
+        count_stubs: matches.get_flag("count-stubs"),
+        long_function_threshold: matches.get_one::<usize>("count-long-methods").copied().or(project.count_long_methods),
+        directory_tree: matches.get_flag("tree"),
+        by_file: matches.get_flag("by-file"),
+        deps_graph: matches.contains_id("deps-graph"),
+        by_year: matches.get_flag("by-year"),
+        todos_by_author: matches.get_flag("todos-by-author"),
+        by_extension: matches.get_flag("by-ext"),
+        duplicate_block_lines: matches.get_one::<usize>("find-dupes").copied().or(project.find_dupes),
+        json_stream: (format == "json-array" && !matches.contains_id("diff"))
+            .then(|| Arc::new(JsonArrayStream::new(std::io::stdout()))),
+        cancelled: Arc::clone(&cancelled),
+        progress,
+    };
+    let start_time = Instant::now();
+    
+    let keep_patterns: Vec<String> = matches.get_many::<String>("keep")
+        .map(|patterns| patterns.cloned().collect())
+        .unwrap_or_default();
+    // A directory list from the command line, or else from .rcloc.toml
+    let dir_list = |id: &str, configured: &Option<Vec<String>>| match configured {
+        Some(dirs) if !given(&matches, id) => parse_dir_list(dirs.iter().map(String::as_str)),
+        _ => parse_dir_list(matches.get_one::<String>(id).into_iter().flat_map(|dirs| dirs.split(','))),
+    };
+    // Files below `root` that pass the exclusion, ignore and --keep filters
+    let collect = |root: &str| {
+        let mut scan_options = ScanOptions {
+            exclude_dirs: dir_list("exclude-dirs", &project.exclude_dirs),
+            include_dirs: dir_list("include-dirs", &project.include_dirs),
+            no_default_excludes: matches.get_flag("no-default-excludes"),
+            no_ignore: matches.get_flag("no-ignore"),
+            ignore_files: [("respect-dockerignore", ".dockerignore"), ("respect-npmignore", ".npmignore")]
+                .into_iter()
+                .filter(|(flag, _)| matches.get_flag(flag))
+                .map(|(_, name)| name.to_string())
+                .collect(),
+            cancelled: Arc::clone(&cancelled),
+            progress: options.progress.clone(),
+            ..Default::default()
+        };
+        if !keep_patterns.is_empty() {
+            match build_keep_matcher(Path::new(root), &keep_patterns) {
+                Ok(keep) => scan_options.keep = Some(keep),
+                Err(e) => {
+                    eprintln!("Invalid --keep pattern: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        collect_files(Path::new(root), &lang_db, &scan_options)
+    };
+    
+    let mut result = if let Some(filename) = matches.get_one::<String>("stdin-filename") {
+        let filename = Path::new(filename);
+        let Some(lang_config) = lang_db.get_language(filename) else {
+            eprintln!("Unrecognized language for {}", filename.display());
+            std::process::exit(1);
+        };
+        match analyze_stdin(std::io::stdin(), filename, Arc::clone(lang_config), &options) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("Failed to read stdin: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        options.progress.report(&format!("Analyzing directory: {}", path));
+        let files = collect(path);
+        
+        // Under --diff an empty side is a valid baseline, and json-array
+        // still prints an array holding just the summary
+        if files.is_empty() && diff_path.is_none() {
+            eprintln!("No supported files found!");
+            if options.json_stream.is_none() {
+                return;
+            }
+        }
+        
+        analyze_files(files, &options)
+    };
+    
+    result.sum_label = matches.get_one::<String>("sum-label").cloned();
+    result.by_extension = options.by_extension;
+    
+    if let Some(other) = &diff_path {
+        options.progress.report(&format!("Analyzing directory: {}", other));
+        let baseline = analyze_files(collect(other), &options);
+        let diff = Diff::between(&result, &baseline);
+        result.binary_files += baseline.binary_files;
+        result.unreadable_files += baseline.unreadable_files;
+        result.interrupted |= baseline.interrupted;
+        result.duration = start_time.elapsed();
+        
+        match format {
+            "json" | "json-array" => println!("{}", serde_json::to_string_pretty(&diff.to_json()).unwrap()),
+            "csv" => {
+                if let Err(e) = diff.to_csv(&mut std::io::stdout().lock()) {
+                    eprintln!("Failed to write CSV: {}", e);
+                }
+            }
+            _ => {
+                println!();
+                diff.print_table();
+                println!();
+            }
+        }
+        print_run_notes(&result, &options.progress);
+        return;
+    }
+    
+    result.duration = start_time.elapsed();
+    
+    match format {
+        "json" => {
+            warn_text_only_reports(&matches);
+            let json = if options.by_file { result.files_to_json() } else { result.to_json() };
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+        }
+        "json-array" => {
+            warn_text_only_reports(&matches);
+            if let Some(stream) = &options.json_stream {
+                if let Err(e) = stream.finish(&serde_json::json!({ "summary": result.to_json() })) {
+                    eprintln!("Failed to write JSON: {}", e);
+                }
+            }
+        }
+        "csv" => {
+            warn_text_only_reports(&matches);
+            let mut stdout = std::io::stdout().lock();
+            let written = if options.by_file { result.files_to_csv(&mut stdout) } else { result.to_csv(&mut stdout) };
+            if let Err(e) = written {
+                eprintln!("Failed to write CSV: {}", e);
+            }
+        }
+        _ => {
+            println!();
+            if options.count_stubs {
+                print_stub_functions(&result.languages, result.sum_label());
+                println!();
+            }
+            if options.by_file {
+                result.print_file_table();
+                println!();
+            }
+            result.print_table();
+            println!();
+            if let Some(threshold) = options.long_function_threshold {
+                result.print_long_functions(threshold);
+                println!();
+            }
+            if matches.get_flag("comment-only-files") {
+                print_comment_only_files(&result.languages, result.sum_label());
+                println!();
+            }
+            if matches.get_flag("comment-density") {
+                result.print_density_histogram();
+                println!();
+            }
+            if options.directory_tree {
+                result.print_directory_tree(Path::new(path));
+                println!();
+            }
+            if options.by_year {
+                result.print_years();
+                println!();
+            }
+            if options.todos_by_author {
+                result.print_todo_authors();
+                println!();
+            }
+            if let Some(min_lines) = options.duplicate_block_lines {
+                result.print_duplicate_blocks(min_lines);
+                println!();
+            }
+            if let Some(range) = matches.get_one::<String>("churn") {
+                match git_churn(Path::new(path), range, &lang_db) {
+                    Ok(churn) => print_churn(&churn, range, result.sum_label()),
+                    Err(e) => eprintln!("Failed to read git history for {}: {}", range, e),
+                }
+                println!();
+            }
+            if let Some(range) = matches.get_one::<String>("comment-only-changes") {
+                match git_change_kinds(Path::new(path), range, &lang_db) {
+                    Ok(changes) => print_change_kinds(&changes, range),
+                    Err(e) => eprintln!("Failed to read git diff for {}: {}", range, e),
+                }
+                println!();
+            }
+        }
+    }
+    if let Some(target) = matches.get_one::<String>("deps-graph") {
+        match result.write_deps_graph(Path::new(target)) {
+            Ok(()) => options.progress.report(&format!("Dependency graph written to {}", target)),
+            Err(e) => eprintln!("Failed to write dependency graph to {}: {}", target, e),
+        }
+    }
+    print_run_notes(&result, &options.progress);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Blank, comment and code lines of `content` analyzed as the language
+    // that `filename` is detected as
+    fn count(filename: &str, content: &str) -> (u64, u64, u64) {
+        let lang_db = LanguageDatabase::new();
+        let lang_config = lang_db.get_language(Path::new(filename)).expect("known language");
+        let stats = FileAnalyzer::new(Arc::clone(lang_config)).analyze_reader(content.as_bytes()).unwrap();
+        (stats.blank_lines, stats.comment_lines, stats.code_lines)
+    }
+
+    // A temporary directory holding `files`, given as relative path and
+    // content pairs
+    fn fixture(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn analyze(root: &Path, options: &AnalysisOptions) -> AnalysisResult {
+        let files = collect_files(root, &LanguageDatabase::new(), &ScanOptions::default());
+        analyze_files(files, options)
+    }
+
+    #[test]
+    fn bom_only_and_whitespace_only_files_are_blank() {
+        assert_eq!(count("a.rs", "\u{feff}"), (1, 0, 0));
+        assert_eq!(count("a.rs", "\u{feff}\n"), (1, 0, 0));
+        assert_eq!(count("a.rs", "  \n\t\n\n"), (3, 0, 0));
+        assert_eq!(count("a.rs", "\u{feff}fn main() {}\n"), (0, 0, 1));
+    }
+
+    #[test]
+    fn skip_empty_leaves_out_files_without_content() {
+        let dir = fixture(&[("bom.rs", "\u{feff}"), ("space.rs", " \n\n"), ("main.rs", "fn main() {}\n")]);
+        assert_eq!(analyze(dir.path(), &AnalysisOptions::default()).total().files, 3);
+        
+        let skipped = analyze(dir.path(), &AnalysisOptions { skip_empty: true, ..Default::default() }).total();
+        assert_eq!((skipped.files, skipped.blank_lines, skipped.code_lines), (1, 0, 1));
+    }
+
+    // A tree of `count` Rust files of different lengths, spread over
+    // several directories
+    fn fixture_many(count: usize) -> tempfile::TempDir {
+        let files: Vec<(String, String)> = (0..count)
+            .map(|i| (format!("dir{}/file{}.rs", i % 7, i), "// note\nfn f() {}\n\n".repeat(1 + i % 5)))
+            .collect();
+        let files: Vec<(&str, &str)> = files.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect();
+        fixture(&files)
+    }
+
+    #[test]
+    fn shared_language_config_gives_same_results_as_copies() {
+        let dir = fixture(&[("a.rs", "// a\nfn a() {}\n"), ("b.rs", "\nfn b() {}\n"), ("c.py", "# c\nx = 1\n")]);
+        let files = collect_files(dir.path(), &LanguageDatabase::new(), &ScanOptions::default());
+        assert!(Arc::ptr_eq(&files[0].1, &files[1].1));
+        
+        let copied: Vec<_> = files.iter()
+            .map(|(path, lang_config)| (path.clone(), Arc::new(LanguageConfig::clone(lang_config))))
+            .collect();
+        let shared = analyze_files(files, &AnalysisOptions::default());
+        let copied = analyze_files(copied, &AnalysisOptions::default());
+        assert_eq!(shared.to_json(), copied.to_json());
+    }
+
+    // Analysis with one LanguageConfig shared per language against a copy
+    // per file. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_shared_language_config() {
+        let dir = fixture_many(20_000);
+        let files = collect_files(dir.path(), &LanguageDatabase::new(), &ScanOptions::default());
+        
+        let start = Instant::now();
+        let shared = analyze_files(files.clone(), &AnalysisOptions::default());
+        println!("shared: {:?}", start.elapsed());
+        
+        let start = Instant::now();
+        let copied: Vec<_> = files.iter()
+            .map(|(path, lang_config)| (path.clone(), Arc::new(LanguageConfig::clone(lang_config))))
+            .collect();
+        let copied = analyze_files(copied, &AnalysisOptions::default());
+        println!("copied: {:?}", start.elapsed());
+        assert_eq!(shared.to_json(), copied.to_json());
+    }
+
+    // Stub functions that --count-stubs finds in `content`
+    fn stubs(filename: &str, content: &str) -> u64 {
+        let lang_db = LanguageDatabase::new();
+        let analyzer = FileAnalyzer::new(Arc::clone(lang_db.get_language(Path::new(filename)).unwrap()));
+        let functions = analyzer.find_functions_in(content);
+        let options = AnalysisOptions { count_stubs: true, ..Default::default() };
+        let mut stats = FileStats::default();
+        apply_function_metrics(&functions, Path::new(filename), &analyzer.lang_config, &options, &mut stats);
+        stats.stub_functions
+    }
+
+    #[test]
+    fn placeholder_bodies_are_stubs() {
+        assert_eq!(stubs("a.rs", "fn later() {\n    unimplemented!()\n}\n\nfn real() -> u32 {\n    1\n}\n"), 1);
+        assert_eq!(stubs("a.rs", "fn empty() {}\nfn commented() {\n    // soon\n}\n"), 2);
+        assert_eq!(stubs("a.py", "def later():\n    pass\n\ndef real():\n    return 1\n"), 1);
+        assert_eq!(stubs("a.py", "def later():\n    \"\"\"Soon.\"\"\"\n    pass\n"), 1);
+        assert_eq!(stubs("a.java", "void later() {\n    throw new UnsupportedOperationException();\n}\n"), 1);
+    }
+
+    #[test]
+    fn per_file_output_is_identical_across_runs() {
+        let dir = fixture_many(300);
+        let options = AnalysisOptions { by_file: true, ..Default::default() };
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        let run = || {
+            let result = pool.install(|| analyze(dir.path(), &options));
+            let mut csv = Vec::new();
+            result.files_to_csv(&mut csv).unwrap();
+            (result.files_to_json(), csv)
+        };
+        
+        let (json, csv) = run();
+        for _ in 0..3 {
+            assert_eq!(run(), (json.clone(), csv.clone()));
+        }
+        let files = json.as_array().unwrap();
+        assert_eq!(files.len(), 300);
+        assert!(files.windows(2).all(|pair| pair[0]["code_lines"].as_u64() >= pair[1]["code_lines"].as_u64()));
+    }
+
+    // Paths of the files collect_files finds below `root`, relative to it
+    fn collected(root: &Path, options: &ScanOptions) -> Vec<PathBuf> {
+        collect_files(root, &LanguageDatabase::new(), options).into_iter()
+            .map(|(path, _)| path.strip_prefix(root).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn keep_restricts_analysis_to_matching_files() {
+        let dir = fixture(&[
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/util/mod.rs", "fn util() {}\n"),
+            ("tests/it.rs", "fn it() {}\n"),
+            ("build.rs", "fn main() {}\n"),
+        ]);
+        let keep = build_keep_matcher(dir.path(), &["src/**".to_string()]).unwrap();
+        let options = ScanOptions { keep: Some(keep), ..Default::default() };
+        assert_eq!(collected(dir.path(), &options), [Path::new("src/main.rs"), Path::new("src/util/mod.rs")]);
+        
+        // A file PATH is matched relative to its directory
+        let file = dir.path().join("src/main.rs");
+        for (pattern, kept) in [("main.rs", 1), ("/main.rs", 1), ("src/**", 0)] {
+            let keep = build_keep_matcher(&file, &[pattern.to_string()]).unwrap();
+            let options = ScanOptions { keep: Some(keep), ..Default::default() };
+            assert_eq!(collect_files(&file, &LanguageDatabase::new(), &options).len(), kept, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn analysis_result_total_and_json() {
+        let dir = fixture(&[("a.rs", "// a\nfn a() {}\n\n"), ("b.rs", "fn b() {}\nfn c() {}\n"), ("c.py", "# c\nx = 1\n")]);
+        let result = analyze(dir.path(), &AnalysisOptions::default());
+        let total = result.total();
+        assert_eq!((total.files, total.blank_lines, total.comment_lines, total.code_lines), (3, 1, 2, 4));
+        
+        let json = result.to_json();
+        assert_eq!(json, serde_json::json!({
+            "Rust": { "files": 2, "blank_lines": 1, "comment_lines": 1, "code_lines": 3 },
+            "Python": { "files": 1, "blank_lines": 0, "comment_lines": 1, "code_lines": 1 },
+            "SUM": { "files": 3, "blank_lines": 1, "comment_lines": 2, "code_lines": 4 },
+        }));
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["Rust", "Python", "SUM"]);
+    }
+
+    #[test]
+    fn gettext_comments_and_entries() {
+        let po = "# Translation of the app\n#, fuzzy\n#: src/main.c:12\nmsgid \"Hello # world\"\nmsgstr \"Bonjour # monde\"\n\nmsgid \"Bye\"\nmsgstr \"\"\n";
+        assert_eq!(count("fr.po", po), (1, 3, 4));
+        assert_eq!(count("app.pot", "# header\nmsgid \"x\"\nmsgstr \"\"\n"), (0, 1, 2));
+    }
+
+    #[test]
+    fn only_functions_over_the_threshold_are_long() {
+        let content = format!("fn short() {{\n    1;\n}}\n\nfn long() {{\n{}}}\n", "    step();\n".repeat(10));
+        let lang_db = LanguageDatabase::new();
+        let analyzer = FileAnalyzer::new(Arc::clone(lang_db.get_language(Path::new("a.rs")).unwrap()));
+        let functions = analyzer.find_functions_in(&content);
+        let options = AnalysisOptions { long_function_threshold: Some(5), ..Default::default() };
+        let long = apply_function_metrics(&functions, Path::new("a.rs"), &analyzer.lang_config, &options, &mut FileStats::default());
+        assert_eq!(long.len(), 1);
+        assert_eq!((long[0].start_line, long[0].lines), (5, 12));
+        
+        let python = "def short():\n    return 1\n\ndef long():\n    a = 1\n    b = 2\n    c = 3\n    d = 4\n    return a\n";
+        let analyzer = FileAnalyzer::new(Arc::clone(lang_db.get_language(Path::new("a.py")).unwrap()));
+        let long = apply_function_metrics(&analyzer.find_functions_in(python), Path::new("a.py"), &analyzer.lang_config, &options, &mut FileStats::default());
+        assert_eq!(long.iter().map(|function| (function.start_line, function.lines)).collect::<Vec<_>>(), [(4, 6)]);
+    }
+
+    #[test]
+    fn stdin_filename_selects_the_language() {
+        let lang_db = LanguageDatabase::new();
+        let filename = Path::new("tools/x.py");
+        let lang_config = lang_db.get_language(filename).unwrap();
+        let content = "#!/usr/bin/env python\n# comment\nimport os  # trailing\n\nprint(os.name)\n";
+        let result = analyze_stdin(content.as_bytes(), filename, Arc::clone(lang_config), &AnalysisOptions::default()).unwrap();
+        
+        let python = &result.languages["Python"];
+        assert_eq!((python.files, python.blank_lines, python.comment_lines, python.code_lines), (1, 1, 1, 3));
+        assert_eq!(result.languages.len(), 1);
+    }
+
+    #[test]
+    fn rescript_and_reasonml_are_distinct() {
+        let lang_db = LanguageDatabase::new();
+        let name = |path: &str| lang_db.get_language(Path::new(path)).map(|lang| lang.name.clone());
+        assert_eq!(name("App.res").as_deref(), Some("ReScript"));
+        assert_eq!(name("App.resi").as_deref(), Some("ReScript"));
+        assert_eq!(name("App.re").as_deref(), Some("ReasonML"));
+        assert_eq!(name("lexer.mll").as_deref(), Some("OCaml"));
+        assert_eq!(name("parser.mly").as_deref(), Some("OCaml"));
+        
+        let res = "// Greeting\n/* multi\n   line */\nlet greet = name => `Hello ${name} // not a comment`\n";
+        assert_eq!(count("App.res", res), (0, 3, 1));
+    }
+
+    #[test]
+    fn comment_density_buckets() {
+        let dir = fixture(&[
+            ("none.rs", "fn a() {}\n"),
+            ("fifth.rs", "// c\nfn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\n"),
+            ("boundary.rs", "// c\nfn a() {}\nfn b() {}\nfn c() {}\n"),
+            ("half.rs", "// c\nfn a() {}\n"),
+            ("empty.rs", "\n"),
+            ("notes.py", "# c\n# d\nx = 1\n"),
+        ]);
+        let result = analyze(dir.path(), &AnalysisOptions::default());
+        assert_eq!(result.density["Rust"], [1, 1, 1, 1]);
+        assert_eq!(result.density["Python"], [0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn scss_and_less_line_comments_outside_strings() {
+        let scss = "// Theme\n$base: #333; // trailing\n.logo { background: url(http://example.com/a.png); }\n.a { content: \"// not a comment\"; }\n/* block */\n";
+        assert_eq!(count("theme.scss", scss), (0, 2, 3));
+        assert_eq!(count("theme.less", "// c\n@import url(//cdn.example.com/x.css);\n"), (0, 1, 1));
+        // Plain CSS has no line comments
+        assert_eq!(count("site.css", "a { background: url(http://x/y.png); }\n/* c */\n"), (0, 1, 1));
+    }
+
+    #[test]
+    fn exclude_and_include_dirs() {
+        let options = ScanOptions {
+            exclude_dirs: parse_dir_list("Build_Output, third_party".split(',')),
+            ..Default::default()
+        };
+        assert!(should_skip_path(Path::new("app/build_output/gen.rs"), &options));
+        assert!(should_skip_path(Path::new("app/THIRD_PARTY/lib.c"), &options));
+        assert!(should_skip_path(Path::new("app/target/debug/x.rs"), &options));
+        assert!(!should_skip_path(Path::new("app/src/third_party_shim.rs"), &options));
+        assert!(!should_skip_path(Path::new("app/src/main.rs"), &options));
+        
+        let dir = fixture(&[("bin/tool.rs", "fn main() {}\n"), ("src/main.rs", "fn main() {}\n"), ("vendor/dep.rs", "fn dep() {}\n")]);
+        assert_eq!(collected(dir.path(), &ScanOptions::default()), [Path::new("src/main.rs")]);
+        let options = ScanOptions { include_dirs: vec!["bin".to_string()], ..Default::default() };
+        assert_eq!(collected(dir.path(), &options), [Path::new("bin/tool.rs"), Path::new("src/main.rs")]);
+        let options = ScanOptions { no_default_excludes: true, ..Default::default() };
+        assert_eq!(collected(dir.path(), &options).len(), 3);
+    }
+
+    #[test]
+    fn comment_only_files_are_counted_separately() {
+        let dir = fixture(&[
+            ("license.rs", "// Copyright\n// SPDX-License-Identifier: MIT\n\n/* more */\n"),
+            ("main.rs", "// entry\nfn main() {}\n"),
+            ("empty.rs", ""),
+        ]);
+        let result = analyze(dir.path(), &AnalysisOptions::default());
+        assert_eq!(result.languages["Rust"].comment_only_files, 1);
+        assert_eq!(result.languages["Rust"].files, 3);
+    }
+
+    #[test]
+    fn cancellation_stops_the_scan_and_marks_results_partial() {
+        let dir = fixture_many(20);
+        let lang_db = LanguageDatabase::new();
+        let scan_options = ScanOptions::default();
+        let files = collect_files(dir.path(), &lang_db, &scan_options);
+        assert_eq!(files.len(), 20);
+        
+        let options = AnalysisOptions::default();
+        let complete = analyze_files(files.clone(), &options);
+        assert!(!complete.interrupted);
+        assert_eq!(complete.total().files, 20);
+        
+        // Files not yet analyzed when the flag is set are skipped, and the
+        // result says it is partial
+        options.cancelled.store(true, Ordering::Relaxed);
+        let partial = analyze_files(files, &options);
+        assert!(partial.interrupted);
+        assert_eq!(partial.total().files, 0);
+        
+        scan_options.cancelled.store(true, Ordering::Relaxed);
+        assert!(collect_files(dir.path(), &lang_db, &scan_options).is_empty());
+    }
+
+    #[test]
+    fn single_line_block_comment_is_a_comment() {
+        assert_eq!(count("a.c", "/* x */\n"), (0, 1, 0));
+        assert_eq!(count("a.ml", "(* x *)\n"), (0, 1, 0));
+        assert_eq!(count("a.c", "/* x */ /* y */\n"), (0, 1, 0));
+    }
+
+    #[test]
+    fn code_around_single_line_block_comment_is_code() {
+        assert_eq!(count("a.c", "int x; /* x */\n/* x */ int y;\n"), (0, 0, 2));
+        assert_eq!(count("a.ml", "let x = 1 (* x *)\n"), (0, 0, 1));
+    }
+
+    #[test]
+    fn rust_comment_markers_inside_strings() {
+        assert_eq!(count("a.rs", "let url = \"http://example.com\";\n"), (0, 0, 1));
+        assert_eq!(count("a.rs", "println!(\"// not a comment\");\n"), (0, 0, 1));
+        assert_eq!(count("a.rs", "let s = \"\\\" /*\";\nlet t = 1;\n"), (0, 0, 2));
+        assert_eq!(count("a.rs", "let r = r#\"a \"/*\" b\"#;\nlet t = 1;\n"), (0, 0, 2));
+        // '"' is a char literal, not the start of a string hiding the /*
+        assert_eq!(count("a.rs", "let q = '\"'; /* open\nstill comment */\n"), (0, 1, 1));
+        // Lifetimes are not strings
+        assert_eq!(count("a.rs", "fn f<'a>(x: &'a str) {} // c\n// c\n"), (0, 1, 1));
+    }
+
+    #[test]
+    fn c_like_comment_markers_inside_strings() {
+        for file in ["a.c", "a.java", "a.cs", "a.php", "a.sql"] {
+            assert_eq!(count(file, "x = \"/*\"; y = '/*';\nz = 1;\n"), (0, 0, 2), "{}", file);
+            assert_eq!(count(file, "x = \"a\\\"/*\";\nz = 1;\n"), (0, 0, 2), "{}", file);
+        }
+        for file in ["a.sh", "a.rb", "a.ps1", "a.php"] {
+            assert_eq!(count(file, "echo \"#\" '#'\n# comment\n"), (0, 1, 1), "{}", file);
+        }
+        assert_eq!(count("a.sql", "SELECT '--' AS dashes; -- trailing\n-- comment\n"), (0, 1, 1));
+        assert_eq!(count("a.ps1", "Write-Host \"<# not\"\n$x = 1\n"), (0, 0, 2));
+    }
+
+    #[test]
+    fn template_string_comment_markers() {
+        for file in ["a.js", "a.ts", "a.go"] {
+            assert_eq!(count(file, "s = `http://${host}/*`;\nt = 1;\n"), (0, 0, 2), "{}", file);
+            assert_eq!(count(file, "s = '/*' + \"//\"; // c\n// c\n"), (0, 1, 1), "{}", file);
+        }
+        assert_eq!(count("a.res", "let s = `/* ${x}`\nlet t = 1\n"), (0, 0, 2));
+    }
+
+    #[test]
+    fn python_triple_quotes_as_strings_and_docstrings() {
+        assert_eq!(count("a.py", "\"\"\"Module doc.\n\nMore.\n\"\"\"\n"), (1, 3, 0));
+        assert_eq!(count("a.py", "'''One-line doc.'''\n"), (0, 1, 0));
+        assert_eq!(count("a.py", "x = \"\"\"a # b\"\"\"\ny = 1\n"), (0, 0, 2));
+        assert_eq!(count("a.py", "t = '\"\"\"'  # quotes\nu = \"#\"\n"), (0, 0, 2));
+    }
+
+    #[test]
+    fn double_quote_only_languages() {
+        assert_eq!(count("a.s", "msg: .ascii \"; not # a comment\"\n; comment\n"), (0, 1, 1));
+        assert_eq!(count("a.S", "msg: .ascii \"/* x\"\nmov r0, r1\n"), (0, 0, 2));
+        assert_eq!(count("a.ml", "let s = \"(* x\"\nlet t = 1\n"), (0, 0, 2));
+        assert_eq!(count("a.re", "let s = \"// x\";\n// c\n"), (0, 1, 1));
+        assert_eq!(count("a.po", "msgid \"# x\"\n# c\n"), (0, 1, 1));
+    }
+
+    #[test]
+    fn csv_rows_follow_table_order_with_total_last() {
+        let dir = fixture(&[("a.py", "# c\nx = 1\n"), ("b.rs", "fn b() {}\nfn c() {}\n"), ("c, d.js", "let x = 1;\n")]);
+        let result = analyze(dir.path(), &AnalysisOptions::default());
+        let mut csv = Vec::new();
+        result.to_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+language,files,blank_lines,comment_lines,code_lines
+Rust,1,0,0,2
+JavaScript,1,0,0,1
+Python,1,0,1,1
+SUM,3,0,1,4
+");
+        assert_eq!(result.to_json()["Rust"]["code_lines"], 2);
+        assert_eq!(result.to_json()["SUM"]["files"], 3);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("Rust"), "Rust");
+        assert_eq!(csv_field("C, C++"), "\"C, C++\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn raw_and_preprocessed_assembly_are_distinct() {
+        let db = LanguageDatabase::new();
+        assert_eq!(db.get_language(Path::new("boot.s")).unwrap().name, "Assembly");
+        assert_eq!(db.get_language(Path::new("boot.S")).unwrap().name, "Assembly (cpp)");
+        let source = "#include <asm.h>\n/* entry */\n// setup\nmov r0, r1 ; load\n";
+        assert_eq!(count("boot.s", source), (0, 1, 3));
+        assert_eq!(count("boot.S", source), (0, 2, 2));
+    }
+
+    #[test]
+    fn directory_totals_are_the_sum_of_their_files() {
+        let dir = fixture(&[
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/net/a.rs", "fn a() {}\nfn b() {}\n"),
+            ("src/net/b.rs", "// c\nfn c() {}\n"),
+            ("src/net/deep/c.rs", "fn d() {}\n\nfn e() {}\n"),
+        ]);
+        let options = AnalysisOptions { directory_tree: true, ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        let total = |path: &str| result.directories[&dir.path().join(path)];
+        assert_eq!(total("src/net/deep"), 2);
+        assert_eq!(total("src/net"), 2 + 1 + 2);
+        assert_eq!(total("src"), 1 + 5);
+        assert_eq!(result.directories[dir.path()], 6);
+    }
+
+    #[test]
+    fn by_file_sorts_by_code_lines_then_path() {
+        let dir = fixture(&[
+            ("b.rs", "fn b() {}\n"),
+            ("a.rs", "fn a() {}\n"),
+            ("c.py", "# c\nx = 1\ny = 2\n"),
+        ]);
+        let options = AnalysisOptions { by_file: true, ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        let names: Vec<_> = result.by_file().iter().map(|file| file.path.strip_prefix(dir.path()).unwrap().to_path_buf()).collect();
+        assert_eq!(names, [PathBuf::from("c.py"), PathBuf::from("a.rs"), PathBuf::from("b.rs")]);
+        
+        let json = result.files_to_json();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_eq!(json[0]["language"], "Python");
+        assert_eq!(json[0]["comment_lines"], 1);
+        assert_eq!(json[0]["code_lines"], 2);
+        assert!(json[0]["path"].as_str().unwrap().ends_with("c.py"));
+    }
+
+    #[test]
+    fn per_file_rows_are_only_kept_for_by_file() {
+        let dir = fixture(&[("a.rs", "fn a() {}\n")]);
+        assert!(analyze(dir.path(), &AnalysisOptions::default()).files.is_empty());
+    }
+
+    // A language definition that has not been through validate()
+    fn malformed(start: &[&str], end: &[&str]) -> LanguageConfig {
+        LanguageConfig {
+            name: "Broken".to_string(),
+            extensions: vec!["broken".to_string()],
+            line_comment: vec![],
+            block_comment_start: start.iter().map(|token| token.to_string()).collect(),
+            block_comment_end: end.iter().map(|token| token.to_string()).collect(),
+            string_delimiters: vec![],
+        }
+    }
+
+    #[test]
+    fn malformed_block_comments_are_rejected() {
+        let mut db = LanguageDatabase::new();
+        let err = db.add_language(malformed(&["/*", "(*"], &["*/"])).unwrap_err();
+        assert_eq!(err, "Broken: 2 block comment start tokens but 1 end tokens");
+        let err = db.add_language(malformed(&["/*"], &[""])).unwrap_err();
+        assert_eq!(err, "Broken: comment and string tokens must not be empty");
+        assert!(db.get_language(Path::new("a.broken")).is_none());
+    }
+
+    #[test]
+    fn unvalidated_start_without_end_token_is_ignored() {
+        for config in [malformed(&["/*"], &[]), malformed(&["/*"], &[""])] {
+            let stats = FileAnalyzer::new(Arc::new(config)).analyze_reader("x /* a\n/* b\n".as_bytes()).unwrap();
+            assert_eq!((stats.blank_lines, stats.comment_lines, stats.code_lines), (0, 0, 2));
+        }
+    }
+
+    #[test]
+    fn ignore_files_are_honored_unless_no_ignore() {
+        let dir = fixture(&[
+            (".gitignore", "generated/\n*.tmp.rs\n"),
+            (".ignore", "scratch.rs\n"),
+            ("generated/out.rs", "fn out() {}\n"),
+            ("lib.tmp.rs", "fn tmp() {}\n"),
+            ("scratch.rs", "fn scratch() {}\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("target/debug/build.rs", "fn build() {}\n"),
+        ]);
+        assert_eq!(collected(dir.path(), &ScanOptions::default()), [Path::new("src/main.rs")]);
+        
+        // The built-in skip list still applies without ignore files
+        let options = ScanOptions { no_ignore: true, ..Default::default() };
+        assert_eq!(collected(dir.path(), &options), [
+            Path::new("generated/out.rs"),
+            Path::new("lib.tmp.rs"),
+            Path::new("scratch.rs"),
+            Path::new("src/main.rs"),
+        ]);
+    }
+
+    #[test]
+    fn imports_are_extracted_per_language() {
+        let db = LanguageDatabase::new();
+        let rust = "use std::collections::HashMap;\npub use crate::config::*;\nuse serde::{Deserialize, Serialize};\n// use commented::out;\nextern crate rayon;\nfn main() {}\n";
+        assert_eq!(extract_imports(rust, db.get_language(Path::new("a.rs")).unwrap()),
+                   ["crate::config", "rayon", "serde", "std::collections::HashMap"]);
+        let python = "import os, sys as system\nfrom pathlib import Path\n# import hidden\nx = 1\n";
+        assert_eq!(extract_imports(python, db.get_language(Path::new("a.py")).unwrap()), ["os", "pathlib", "sys"]);
+    }
+
+    #[test]
+    fn deps_graph_has_an_edge_per_import() {
+        let dir = fixture(&[("app.py", "import json\nfrom util import helper\n"), ("main.rs", "use std::fs;\nfn main() {}\n")]);
+        let options = AnalysisOptions { deps_graph: true, ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        let target = dir.path().join("deps.dot");
+        result.write_deps_graph(&target).unwrap();
+        let dot = std::fs::read_to_string(&target).unwrap();
+        let edges: Vec<_> = dot.lines().filter(|line| line.contains("->")).collect();
+        assert_eq!(edges.len(), 3);
+        assert!(edges[0].contains("app.py\" -> \"json\""));
+        assert!(edges[1].contains("app.py\" -> \"util\""));
+        assert!(edges[2].contains("main.rs\" -> \"std::fs\""));
+    }
+
+    #[test]
+    fn deps_graph_keys_are_file_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = AnalysisResult {
+            imports: vec![(PathBuf::from("src/lib.rs"), vec!["std::fs".to_string()])],
+            ..Default::default()
+        };
+        let target = dir.path().join("deps.json");
+        result.write_deps_graph(&target).unwrap();
+        let graph: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&target).unwrap()).unwrap();
+        assert_eq!(graph, serde_json::json!({ "src/lib.rs": ["std::fs"] }));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn json_paths_use_forward_slashes_on_windows() {
+        let file = FileResult {
+            path: PathBuf::from(r".\src\main.rs"),
+            language: "Rust".to_string(),
+            stats: FileStats::default(),
+        };
+        assert_eq!(file.to_json()["path"], "./src/main.rs");
+        
+        let dir = tempfile::tempdir().unwrap();
+        let result = AnalysisResult {
+            imports: vec![(PathBuf::from(r"src\lib.rs"), vec!["std::fs".to_string()])],
+            ..Default::default()
+        };
+        let target = dir.path().join("deps.dot");
+        result.write_deps_graph(&target).unwrap();
+        let graph = std::fs::read_to_string(&target).unwrap();
+        assert!(graph.contains(r#""src/lib.rs" -> "std::fs";"#), "{}", graph);
+    }
+
+    #[test]
+    fn stats_of_one_language_add_up_across_files_and_workers() {
+        let rust_file = |name: &str, blank, comment, code| FileResult {
+            path: PathBuf::from(name),
+            language: "Rust".to_string(),
+            stats: FileStats { files: 1, blank_lines: blank, comment_lines: comment, code_lines: code, ..Default::default() },
+        };
+        let options = AnalysisOptions::default();
+        let mut first = AnalysisResult::default();
+        first.add_file(rust_file("a.rs", 1, 2, 3), FileExtras::default(), &options);
+        first.add_file(rust_file("b.rs", 4, 5, 6), FileExtras::default(), &options);
+        let mut second = AnalysisResult::default();
+        second.add_file(rust_file("c.rs", 7, 8, 9), FileExtras::default(), &options);
+        
+        let merged = first.merge(second);
+        let rust = &merged.languages["Rust"];
+        assert_eq!((rust.files, rust.blank_lines, rust.comment_lines, rust.code_lines), (3, 12, 15, 18));
+        let total = merged.total();
+        assert_eq!((total.files, total.blank_lines, total.comment_lines, total.code_lines), (3, 12, 15, 18));
+    }
+
+    #[test]
+    fn csv_rows_are_counted_as_code() {
+        assert_eq!(count("data.csv", "id,name\n1,\"# not a comment\"\n\n2,// nor this\n"), (1, 0, 3));
+        assert_eq!(count("data.tsv", "id\tname\n1\tx\n"), (0, 0, 2));
+    }
+
+    #[test]
+    fn user_languages_load_from_toml_and_json() {
+        let dir = fixture(&[
+            ("langs.toml", "[[language]]\nname = \"Jsonnet\"\nextensions = [\"jsonnet\", \"libsonnet\"]\nline_comment = [\"//\", \"#\"]\nblock_comment_start = [\"/*\"]\nblock_comment_end = [\"*/\"]\n"),
+            ("langs.json", r#"{ "language": [{ "name": "MyPython", "extensions": ["py"], "line_comment": [";"] }] }"#),
+        ]);
+        let mut db = LanguageDatabase::new();
+        db.load_config(&dir.path().join("langs.toml")).unwrap();
+        db.load_config(&dir.path().join("langs.json")).unwrap();
+        assert_eq!(db.get_language(Path::new("a.libsonnet")).unwrap().name, "Jsonnet");
+        
+        // A user definition takes over a built-in extension
+        let lang = db.get_language(Path::new("a.py")).unwrap();
+        assert_eq!(lang.name, "MyPython");
+        let stats = FileAnalyzer::new(Arc::clone(lang)).analyze_reader("; c\n# x\n".as_bytes()).unwrap();
+        assert_eq!((stats.comment_lines, stats.code_lines), (1, 1));
+    }
+
+    #[test]
+    fn bad_language_files_are_reported() {
+        let dir = fixture(&[
+            ("twice.toml", "[[language]]\nname = \"A\"\nextensions = [\"x\"]\n\n[[language]]\nname = \"B\"\nextensions = [\"x\"]\n"),
+            ("typo.toml", "[[language]]\nname = \"A\"\nextension = [\"x\"]\n"),
+            ("broken.json", "{ \"language\": [ }"),
+        ]);
+        let load = |name: &str| LanguageDatabase::new().load_config(&dir.path().join(name)).unwrap_err();
+        assert!(load("twice.toml").ends_with("twice.toml: extension .x is defined by both A and B"));
+        assert!(load("typo.toml").contains("unknown field `extension`"));
+        assert!(load("broken.json").contains("broken.json: "));
+        assert!(load("missing.toml").contains("missing.toml: "));
+    }
+
+    // Runs git in `dir` with a fixed identity, dating any commit at `date`
+    fn git(dir: &Path, date: &str, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    // Commits everything in `dir` as `author` at `date`
+    fn commit_all(dir: &Path, author: &str, date: &str) {
+        git(dir, date, &["add", "-A"]);
+        git(dir, date, &["commit", "-q", "-m", "change", "--author", &format!("{} <{}@example.com>", author, author)]);
+    }
+
+    #[test]
+    fn code_lines_are_split_by_year_of_last_change() {
+        let dir = fixture(&[("a.rs", "// old\nfn a() {}\nfn b() {}\n")]);
+        git(dir.path(), "2019-06-01T12:00:00+0000", &["init", "-q"]);
+        commit_all(dir.path(), "Ann", "2019-06-01T12:00:00+0000");
+        std::fs::write(dir.path().join("a.rs"), "// old\nfn a() {}\nfn b() { 2 }\nfn c() {}\n").unwrap();
+        commit_all(dir.path(), "Ann", "2023-03-01T12:00:00+0000");
+        std::fs::write(dir.path().join("a.rs"), "// old\nfn a() {}\nfn b() { 2 }\nfn c() {}\nfn d() {}\n").unwrap();
+        std::fs::write(dir.path().join("new.rs"), "fn n() {}\n").unwrap();
+        
+        let options = AnalysisOptions { by_year: true, ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        let years: std::collections::BTreeMap<&str, u64> = result.years.iter().map(|(year, lines)| (year.as_str(), *lines)).collect();
+        assert_eq!(years, std::collections::BTreeMap::from([("2019", 1), ("2023", 2), (UNCOMMITTED, 1), (UNTRACKED, 1)]));
+    }
+
+    #[test]
+    fn binary_files_are_detected() {
+        assert!(looks_binary(b"GIF89a\0\x01\x02"));
+        assert!(looks_binary(&[0x01, 0x02, 0x03, 0xff, 0xfe, b'a']));
+        assert!(!looks_binary("fn main() {}\n// é ü\n".as_bytes()));
+        assert!(!looks_binary(b"\x1b[31mred\x1b[0m\tand tabs\r\n"));
+        // A multi-byte character cut off by the end of the chunk
+        assert!(!looks_binary(&"aé".as_bytes()[..2]));
+    }
+
+    #[test]
+    fn binary_and_unreadable_files_are_skipped_and_counted() {
+        let dir = fixture(&[("main.rs", "fn main() {}\n")]);
+        std::fs::write(dir.path().join("blob.rs"), b"\x7fELF\0\0\x01\x02").unwrap();
+        // Mostly valid text with one invalid byte past the sniffed chunk
+        let mut text = "fn g() {}\n".repeat(BINARY_SNIFF_LEN / 10 + 1).into_bytes();
+        text.extend(b"// caf\xe9\n");
+        std::fs::write(dir.path().join("latin1.rs"), text).unwrap();
+        
+        let result = analyze(dir.path(), &AnalysisOptions::default());
+        assert_eq!(result.binary_files, 1);
+        assert_eq!(result.unreadable_files, 1);
+        assert_eq!(result.languages["Rust"].files, 1);
+    }
+
+    #[test]
+    fn shebang_is_code_and_hash_comments_are_comments() {
+        assert_eq!(count("run.py", "#!/usr/bin/env python3\n# comment\nprint(1)\n"), (0, 1, 2));
+        assert_eq!(count("run.sh", "\u{feff}#!/bin/sh\n#comment\necho hi\n"), (0, 1, 2));
+        // Only the first line can be a shebang
+        assert_eq!(count("run.sh", "echo hi\n#!/bin/sh\n"), (0, 1, 1));
+        assert_eq!(count("lib.rs", "#![allow(dead_code)]\n// c\n"), (0, 1, 1));
+    }
+
+    #[test]
+    fn diff_signs_languages_found_on_one_side() {
+        let old = fixture(&[("a.rs", "fn a() {}\n"), ("tool.py", "# c\nx = 1\ny = 2\n")]);
+        let new = fixture(&[("a.rs", "fn a() {}\nfn b() {}\n"), ("app.js", "let x = 1;\n\n")]);
+        let diff = Diff::between(&analyze(new.path(), &AnalysisOptions::default()), &analyze(old.path(), &AnalysisOptions::default()));
+        let rows: Vec<_> = diff.languages.iter().map(|(lang, delta)| (lang.as_str(), *delta)).collect();
+        let delta = |files, blank_lines, comment_lines, code_lines| FileStatsDelta { files, blank_lines, comment_lines, code_lines };
+        assert_eq!(rows, [
+            ("Python", delta(-1, 0, -1, -2)),
+            ("JavaScript", delta(1, 1, 0, 1)),
+            ("Rust", delta(0, 0, 0, 1)),
+        ]);
+        assert_eq!(diff.total, delta(0, 1, -1, 0));
+        assert_eq!(diff.to_json()["Python"]["code_lines"], -2);
+        assert_eq!(diff.to_json()["SUM"]["blank_lines"], 1);
+    }
+
+    #[test]
+    fn duplicated_block_across_two_files() {
+        let block: String = (1..=10).map(|i| format!("let v{} = compute({});\n", i, i)).collect();
+        let dir = fixture(&[
+            ("a.rs", &format!("fn a() {{\n{}}}\n", block)),
+            ("b.rs", &format!("// header\n\nfn b() {{\n    let x = 0;\n{}    x\n}}\n", block.replace("let", "  let"))),
+            ("c.rs", "fn c() {}\n"),
+        ]);
+        let options = AnalysisOptions { duplicate_block_lines: Some(10), ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        let blocks = find_duplicate_blocks(&result.line_hashes, 10);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].lines, 10);
+        assert_eq!(blocks[0].copies, [(dir.path().join("a.rs"), 2, 11), (dir.path().join("b.rs"), 5, 14)]);
+    }
+
+    #[test]
+    fn repeated_line_is_not_a_duplicate_of_itself() {
+        let hashes = |content: &str| vec![(PathBuf::from("a.rs"), line_hashes(content))];
+        assert!(find_duplicate_blocks(&hashes(&"0,\n".repeat(14)), 10).is_empty());
+        
+        let blocks = find_duplicate_blocks(&hashes(&"0,\n".repeat(20)), 10);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].copies, [(PathBuf::from("a.rs"), 1, 10), (PathBuf::from("a.rs"), 11, 20)]);
+    }
+
+    #[test]
+    fn classify_line_carries_block_state_between_lines() {
+        let lang_db = LanguageDatabase::new();
+        let analyzer = FileAnalyzer::new(Arc::clone(lang_db.get_language(Path::new("a.c")).unwrap()));
+        let mut state = BlockState::default();
+        let lines = [
+            ("int x = 1; /* starts", LineType::Code, true),
+            ("still inside", LineType::Comment, true),
+            ("ends */", LineType::Comment, false),
+            ("// line", LineType::Comment, false),
+            ("/* one */ int y;", LineType::Code, false),
+            ("/* open", LineType::Comment, true),
+            ("*/ int z;", LineType::Code, false),
+        ];
+        for (line, expected, in_comment) in lines {
+            assert_eq!(analyzer.classify_line(line, &mut state), expected, "{}", line);
+            assert_eq!(state.in_comment(), in_comment, "{}", line);
+        }
+    }
+
+    #[test]
+    fn churn_sums_added_and_removed_lines_per_language() {
+        let dir = fixture(&[("a.rs", "fn a() {}\nfn b() {}\nfn c() {}\n"), ("tool.py", "x = 1\ny = 2\n")]);
+        std::fs::write(dir.path().join("logo.rs"), b"\0\x01\x02").unwrap();
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["init", "-q"]);
+        commit_all(dir.path(), "Ann", "2024-01-01T12:00:00+0000");
+        std::fs::write(dir.path().join("a.rs"), "fn a() {}\nfn b() { 1 }\nfn c() {}\nfn d() {}\n").unwrap();
+        std::fs::remove_file(dir.path().join("tool.py")).unwrap();
+        commit_all(dir.path(), "Ann", "2024-01-02T12:00:00+0000");
+        
+        let lang_db = LanguageDatabase::new();
+        let churn = git_churn(dir.path(), "HEAD~1..HEAD", &lang_db).unwrap();
+        assert_eq!(churn, HashMap::from([("Rust".to_string(), (2, 1)), ("Python".to_string(), (0, 2))]));
+        let churn = git_churn(dir.path(), "HEAD", &lang_db).unwrap();
+        assert_eq!(churn, HashMap::from([("Rust".to_string(), (5, 1)), ("Python".to_string(), (2, 2))]));
+        assert!(git_churn(dir.path(), "no-such-ref", &lang_db).is_err());
+        assert!(git_churn(dir.path(), "--output=churn.txt", &lang_db).is_err());
+        assert!(!dir.path().join("churn.txt").exists());
+    }
+
+    #[test]
+    fn renamed_numstat_paths_use_the_new_name() {
+        assert_eq!(numstat_path("src/{old => new}/lib.rs"), "src/new/lib.rs");
+        assert_eq!(numstat_path("src/{ => sub}/lib.rs"), "src/sub/lib.rs");
+        assert_eq!(numstat_path("old.py => new.rs"), "new.rs");
+        assert_eq!(numstat_path("main.rs"), "main.rs");
+        assert_eq!(numstat_path("a}b{c.rs"), "a}b{c.rs");
+        assert_eq!(numstat_path("a}b{c.rs => d.rs"), "d.rs");
+    }
+
+    #[test]
+    fn extension_groups_sum_to_the_labeled_total() {
+        let dir = fixture(&[("a.c", "int a;\n"), ("a.h", "// h\nint b;\nint c;\n"), ("b.h", "\nint d;\n"), ("x.py", "x = 1\n")]);
+        let options = AnalysisOptions { by_extension: true, ..Default::default() };
+        let mut result = analyze(dir.path(), &options);
+        result.by_extension = true;
+        result.sum_label = Some("Total".to_string());
+        
+        let groups = result.groups();
+        let keys: Vec<&str> = groups.iter().map(|(ext, _)| ext.as_str()).collect();
+        assert_eq!(keys, ["h", "c", "py"]);
+        let code: u64 = groups.iter().map(|(_, stats)| stats.code_lines).sum();
+        let files: u64 = groups.iter().map(|(_, stats)| stats.files).sum();
+        assert_eq!((files, code), (result.total().files, result.total().code_lines));
+        
+        let json = result.to_json();
+        assert_eq!(json["Total"]["code_lines"], 5);
+        assert_eq!(json["h"]["files"], 2);
+        let mut csv = Vec::new();
+        result.to_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("extension,"));
+        assert!(csv.ends_with("\nTotal,4,1,1,5\n"));
+    }
+
+    #[test]
+    fn language_subtotals_add_up_to_the_grand_total() {
+        let dir = fixture(&[("a.c", "int a;\n"), ("a.h", "// h\nint b;\nint c;\n"), ("b.h", "\nint d;\n"), ("x.py", "x = 1\n")]);
+        let options = AnalysisOptions { by_extension: true, ..Default::default() };
+        let mut result = analyze(dir.path(), &options);
+        result.by_extension = true;
+        
+        let subtotals = result.extensions_by_language();
+        let rows: Vec<(&str, Vec<&str>)> = subtotals.iter()
+            .map(|(lang, extensions)| (lang.as_str(), extensions.iter().map(|(ext, _)| ext.as_str()).collect()))
+            .collect();
+        assert_eq!(rows, [("C/C++", vec!["h", "c"]), ("Python", vec!["py"])]);
+        let counts = |stats: &FileStats| (stats.files, stats.blank_lines, stats.comment_lines, stats.code_lines);
+        let mut grand_total = FileStats::default();
+        for (lang, extensions) in &subtotals {
+            let subtotal = extensions.iter().fold(FileStats::default(), |acc, (_, stats)| acc + (*stats).clone());
+            assert_eq!(counts(&subtotal), counts(&result.languages[*lang]));
+            grand_total += subtotal;
+        }
+        assert_eq!(counts(&grand_total), counts(&result.total()));
+    }
+
+    #[test]
+    fn dockerignore_is_only_honored_when_requested() {
+        let dir = fixture(&[(".dockerignore", "tests/\n"), ("src/main.rs", "fn main() {}\n"), ("tests/it.rs", "fn it() {}\n")]);
+        assert_eq!(collected(dir.path(), &ScanOptions::default()), [Path::new("src/main.rs"), Path::new("tests/it.rs")]);
+        let options = ScanOptions { ignore_files: vec![".dockerignore".to_string()], ..Default::default() };
+        assert_eq!(collected(dir.path(), &options), [Path::new("src/main.rs")]);
+        // Requested ignore files still apply with --no-ignore
+        let options = ScanOptions { no_ignore: true, ..options };
+        assert_eq!(collected(dir.path(), &options), [Path::new("src/main.rs")]);
+    }
+
+    #[test]
+    fn stats_addition_saturates() {
+        let big = FileStats { files: u64::MAX - 1, blank_lines: u64::MAX, comment_lines: 1, code_lines: u64::MAX - 5, ..Default::default() };
+        let small = FileStats { files: 3, blank_lines: 1, comment_lines: 2, code_lines: 5, stub_functions: 1, ..Default::default() };
+        let sum = big.clone() + small.clone();
+        assert_eq!((sum.files, sum.blank_lines, sum.comment_lines, sum.code_lines), (u64::MAX, u64::MAX, 3, u64::MAX));
+        
+        let mut acc = small;
+        acc += big;
+        assert_eq!((acc.files, acc.code_lines, acc.stub_functions), (u64::MAX, u64::MAX, 1));
+    }
+
+    #[test]
+    fn todos_are_attributed_to_their_authors() {
+        let dir = fixture(&[("a.rs", "// TODO: first\nfn a() {}\n")]);
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["init", "-q"]);
+        commit_all(dir.path(), "Ann", "2024-01-01T12:00:00+0000");
+        std::fs::write(dir.path().join("a.rs"), "// TODO: first\nfn a() {}\nfn b() {} // FIXME later\nlet s = \"TODO\"; /* TODO */\nlet TODO_LIST = 1;\n").unwrap();
+        commit_all(dir.path(), "Bob", "2024-01-02T12:00:00+0000");
+        std::fs::write(dir.path().join("b.rs"), "fn c() {} // TODO\n").unwrap();
+        
+        let options = AnalysisOptions { todos_by_author: true, ..Default::default() };
+        let result = analyze(dir.path(), &options);
+        assert_eq!(result.todo_authors, HashMap::from([
+            ("Ann".to_string(), 1),
+            ("Bob".to_string(), 2),
+            (UNTRACKED.to_string(), 1),
+        ]));
+    }
+
+    // A writer whose output stays readable after it is handed off
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // The --format json-array output for the tree at `root`
+    fn json_array(root: &Path) -> serde_json::Value {
+        let buffer = SharedBuffer::default();
+        let stream = Arc::new(JsonArrayStream::new(buffer.clone()));
+        let options = AnalysisOptions { json_stream: Some(Arc::clone(&stream)), ..Default::default() };
+        let result = analyze(root, &options);
+        stream.finish(&serde_json::json!({ "summary": result.to_json() })).unwrap();
+        let output = buffer.0.lock().unwrap().clone();
+        serde_json::from_slice(&output).unwrap()
+    }
+
+    #[test]
+    fn json_array_streams_files_in_input_order() {
+        let dir = fixture_many(STREAM_CHUNK_FILES + 300);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        let output = pool.install(|| json_array(dir.path()));
+        for _ in 0..3 {
+            assert_eq!(pool.install(|| json_array(dir.path())), output);
+        }
+        
+        let elements = output.as_array().unwrap();
+        assert_eq!(elements.len(), STREAM_CHUNK_FILES + 300 + 1);
+        let paths: Vec<PathBuf> = collect_files(dir.path(), &LanguageDatabase::new(), &ScanOptions::default())
+            .into_iter().map(|(path, _)| path).collect();
+        for (element, path) in elements.iter().zip(&paths) {
+            assert_eq!(element["path"], portable_path(path));
+        }
+        let summary = &elements.last().unwrap()["summary"];
+        assert_eq!(summary["SUM"]["files"], STREAM_CHUNK_FILES as u64 + 300);
+    }
+
+    #[test]
+    fn json_array_of_an_empty_tree_holds_the_summary() {
+        let dir = fixture(&[("notes.unknown", "text\n")]);
+        let output = json_array(dir.path());
+        assert_eq!(output, serde_json::json!([{ "summary": { "SUM": { "files": 0, "blank_lines": 0, "comment_lines": 0, "code_lines": 0 } } }]));
+    }
+
+    #[test]
+    fn json_has_no_comments_even_with_slashes_in_strings() {
+        let json = "{\n  \"url\": \"http://x\",\n  \"glob\": \"src/**/*.rs\",\n\n  \"n\": 1\n}\n";
+        assert_eq!(count("data.json", json), (1, 0, 5));
+        
+        // With // comments configured, strings still protect the URL
+        let jsonc = LanguageConfig {
+            name: "JSONC".to_string(),
+            extensions: vec!["jsonc".to_string()],
+            line_comment: vec!["//".to_string()],
+            block_comment_start: vec![],
+            block_comment_end: vec![],
+            string_delimiters: string_pairs(&[("\"", "\"")]),
+        };
+        let stats = FileAnalyzer::new(Arc::new(jsonc)).analyze_reader(json.as_bytes()).unwrap();
+        assert_eq!((stats.blank_lines, stats.comment_lines, stats.code_lines), (1, 0, 5));
+    }
+
+    #[test]
+    fn by_extension_keeps_h_and_c_apart_and_adds_up() {
+        let dir = fixture(&[
+            ("a.c", "int a;\nint b;\n"),
+            ("a.h", "int a;\n"),
+            ("Makefile", "all:\n\tcc a.c\n"),
+        ]);
+        let manifest = fixture(&[("langs.json", "{ \"Makefile\": \"Shell\" }")]);
+        let mut lang_db = LanguageDatabase::new();
+        lang_db.load_manifest(&manifest.path().join("langs.json"), dir.path()).unwrap();
+        let files = collect_files(dir.path(), &lang_db, &ScanOptions::default());
+        let options = AnalysisOptions { by_extension: true, ..Default::default() };
+        let mut result = analyze_files(files, &options);
+        result.by_extension = true;
+        
+        let json = result.to_json();
+        assert_eq!(json["c"]["code_lines"], 2);
+        assert_eq!(json["h"]["code_lines"], 1);
+        assert_eq!(json[""]["code_lines"], 2);
+        assert_eq!(json["SUM"]["files"], 3);
+        assert_eq!(json["SUM"]["code_lines"], 5);
+    }
+
+    #[test]
+    fn progress_goes_to_the_progress_file() {
+        let dir = fixture(&[("a.rs", "fn a() {}\n"), ("b.py", "x = 1\n")]);
+        // Its read error is a warning, which stays on stderr
+        std::fs::write(dir.path().join("c.rs"), b"fn c() {}\n// caf\xe9\n").unwrap();
+        let log = tempfile::tempdir().unwrap();
+        let progress = Progress::to_file(&log.path().join("progress.log")).unwrap();
+        let scan_options = ScanOptions { progress: progress.clone(), ..Default::default() };
+        let files = collect_files(dir.path(), &LanguageDatabase::new(), &scan_options);
+        let options = AnalysisOptions { progress, ..Default::default() };
+        assert_eq!(analyze_files(files, &options).unreadable_files, 1);
+        
+        let lines = std::fs::read_to_string(log.path().join("progress.log")).unwrap();
+        assert_eq!(lines, "Scanned 0 files...\nFound 3 files to analyze\nAnalyzed 0/3 files (0.0%)\n");
+    }
+
+    #[test]
+    fn manifest_assigns_languages_by_path() {
+        let dir = fixture(&[("codegen/gen.txt", "# generated\nx = 1\n"), ("notes.txt", "plain\n"), ("a.rs", "fn a() {}\n")]);
+        let manifest = fixture(&[
+            ("langs.json", "{ \"codegen/gen.txt\": \"Python\" }"),
+            ("bad.json", "{ \"notes.txt\": \"Klingon\" }"),
+        ]);
+        let mut lang_db = LanguageDatabase::new();
+        lang_db.load_manifest(&manifest.path().join("langs.json"), dir.path()).unwrap();
+        assert_eq!(lang_db.get_language(&dir.path().join("codegen/gen.txt")).unwrap().name, "Python");
+        assert!(lang_db.get_language(&dir.path().join("notes.txt")).is_none());
+        
+        // Under --diff the same entries apply to the other tree
+        let other = fixture(&[("codegen/gen.txt", "x = 1\n")]);
+        lang_db.load_manifest(&manifest.path().join("langs.json"), other.path()).unwrap();
+        assert_eq!(lang_db.get_language(&other.path().join("codegen/gen.txt")).unwrap().name, "Python");
+        assert_eq!(lang_db.get_language(&dir.path().join("codegen/gen.txt")).unwrap().name, "Python");
+        
+        let result = analyze_files(collect_files(dir.path(), &lang_db, &ScanOptions::default()), &AnalysisOptions::default());
+        let python = &result.languages["Python"];
+        assert_eq!((python.files, python.comment_lines, python.code_lines), (1, 1, 1));
+        assert_eq!(result.total().files, 2);
+        
+        let err = LanguageDatabase::new().load_manifest(&manifest.path().join("bad.json"), dir.path()).unwrap_err();
+        assert!(err.ends_with("unknown language \"Klingon\" for notes.txt"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_prefixes_are_stripped() {
+        assert_eq!(strip_verbatim_prefix(r"\\?\C:\src\app"), r"C:\src\app");
+        assert_eq!(strip_verbatim_prefix(r"\\?\UNC\host\share\app"), r"\\host\share\app");
+        assert_eq!(strip_verbatim_prefix(r"\\?\Volume{1234}\app"), r"\\?\Volume{1234}\app");
+        assert_eq!(strip_verbatim_prefix(r"C:\src"), r"C:\src");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_longer_than_max_path_are_analyzed() {
+        let deep: PathBuf = (0..12).map(|i| format!("node_modules_package_{:02}", i)).collect();
+        let file = deep.join("index.js");
+        let dir = fixture(&[(file.to_str().unwrap(), "// entry\nmodule.exports = 1;\n")]);
+        assert!(dir.path().join(&file).as_os_str().len() > 260);
+        
+        // canonicalize returns the extended-length form on Windows
+        let verbatim = dir.path().canonicalize().unwrap().display().to_string();
+        assert!(verbatim.starts_with(r"\\?\"));
+        for root in [dir.path().display().to_string(), strip_verbatim_prefix(&verbatim)] {
+            let result = analyze(Path::new(&root), &AnalysisOptions::default());
+            let js = &result.languages["JavaScript"];
+            assert_eq!((js.files, js.comment_lines, js.code_lines), (1, 1, 1));
+            assert_eq!(result.unreadable_files, 0);
+        }
+    }
+
+    #[test]
+    fn changes_are_classified_despite_prefix_settings() {
+        let dir = fixture(&[
+            ("code.rs", "// add\nfn add(a: i32) -> i32 {\n    a + 1\n}\n"),
+            ("docs.rs", "// old note\nfn docs() {}\n"),
+            ("fmt.py", "x = 1\n"),
+        ]);
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["init", "-q"]);
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["config", "diff.noprefix", "true"]);
+        commit_all(dir.path(), "Ann", "2024-01-01T12:00:00+0000");
+        std::fs::write(dir.path().join("code.rs"), "// add\nfn add(a: i32) -> i32 {\n    a + 2\n}\n").unwrap();
+        std::fs::write(dir.path().join("docs.rs"), "// new note\nfn docs() {} // trailing\n").unwrap();
+        std::fs::write(dir.path().join("fmt.py"), "x  =  1\n").unwrap();
+        
+        let changes = git_change_kinds(dir.path(), "HEAD", &LanguageDatabase::new()).unwrap();
+        assert_eq!(changes, [
+            ("code.rs".to_string(), ChangeKind::Code),
+            ("docs.rs".to_string(), ChangeKind::CommentOnly),
+            ("fmt.py".to_string(), ChangeKind::WhitespaceOnly),
+        ]);
+        
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["config", "--unset", "diff.noprefix"]);
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["config", "diff.mnemonicPrefix", "true"]);
+        assert_eq!(git_change_kinds(dir.path(), "HEAD", &LanguageDatabase::new()).unwrap(), changes);
+    }
+
+    #[test]
+    fn changed_paths_with_spaces_keep_their_language() {
+        let dir = fixture(&[("my notes.rs", "// old note\nfn notes() {}\n")]);
+        git(dir.path(), "2024-01-01T12:00:00+0000", &["init", "-q"]);
+        commit_all(dir.path(), "Ann", "2024-01-01T12:00:00+0000");
+        std::fs::write(dir.path().join("my notes.rs"), "// new note\nfn notes() {}\n").unwrap();
+        
+        let changes = git_change_kinds(dir.path(), "HEAD", &LanguageDatabase::new()).unwrap();
+        assert_eq!(changes, [("my notes.rs".to_string(), ChangeKind::CommentOnly)]);
+        assert!(git_change_kinds(dir.path(), "--output=changes.txt", &LanguageDatabase::new()).is_err());
+        assert!(!dir.path().join("changes.txt").exists());
+    }
+
+    // Comment and string tokens built from characters that appear in real
+    // tokens, including empty ones and, given the small alphabet, duplicates
+    fn token() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just(String::new()),
+            "[/*#\"'(){}<>!=-]{1,3}",
+            "(//|/\\*|\\*/|#|--|\"\"\"|\\(\\*|\\*\\)|<!--|-->|é)",
+        ]
+    }
+
+    // Any language definition, including the malformed ones validate()
+    // rejects: mismatched block comment lists and empty tokens
+    fn unvalidated_config() -> impl Strategy<Value = LanguageConfig> {
+        (
+            prop::collection::vec(token(), 0..4),
+            prop::collection::vec(token(), 0..4),
+            prop::collection::vec(token(), 0..4),
+            prop::collection::vec((token(), token()), 0..4),
+        ).prop_map(|(line_comment, block_comment_start, block_comment_end, string_delimiters)| LanguageConfig {
+            name: "Random".to_string(),
+            extensions: vec!["random".to_string()],
+            line_comment,
+            block_comment_start,
+            block_comment_end,
+            string_delimiters,
+        })
+    }
+
+    // Arbitrary bytes, or text dense with comment and string characters
+    fn content() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..512),
+            "[/*#\"'(){}<>!=\\- a\\\\\n\té]{0,300}".prop_map(String::into_bytes),
+        ]
+    }
+
+    proptest! {
+        // Every hanging case leaves a spinning thread behind, so shrinking
+        // one is kept short
+        #![proptest_config(ProptestConfig { max_shrink_iters: 32, ..ProptestConfig::default() })]
+        
+        #[test]
+        fn classifier_never_panics_or_hangs(config in unvalidated_config(), content in content()) {
+            // A hang fails the case instead of stalling the test run
+            let (done, finished) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let analyzer = FileAnalyzer::new(Arc::new(config));
+                let text = String::from_utf8_lossy(&content).into_owned();
+                let mut state = BlockState::default();
+                for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                    analyzer.classify_line(line, &mut state);
+                }
+                let stats = analyzer.analyze_reader(content.as_slice()).ok();
+                let _ = done.send((text.lines().count() as u64, stats));
+            });
+            let outcome = finished.recv_timeout(Duration::from_secs(2));
+            prop_assert!(outcome.is_ok(), "classifier panicked or did not terminate");
+            let (line_count, stats) = outcome.unwrap();
+            if let Some(stats) = stats {
+                prop_assert_eq!(stats.blank_lines + stats.comment_lines + stats.code_lines, line_count);
+            }
+        }
+    }
+
+    #[test]
+    fn project_config_sets_default_format() {
+        let dir = fixture(&[(".rcloc.toml", "format = \"json\"\n"), ("src/main.rs", "fn main() {}\n")]);
+        let root = dir.path().join("src");
+        let root = root.to_str().unwrap();
+        let project = ProjectConfig::load(&find_project_config(Path::new(root)).unwrap()).unwrap();
+        
+        let matches = cli().get_matches_from(["rcloc", root]);
+        assert_eq!(output_format(&matches, &project), "json");
+        let matches = cli().get_matches_from(["rcloc", root, "--format", "csv"]);
+        assert_eq!(output_format(&matches, &project), "csv");
+        assert_eq!(output_format(&matches, &ProjectConfig::default()), "csv");
+        let matches = cli().get_matches_from(["rcloc", root]);
+        assert_eq!(output_format(&matches, &ProjectConfig::default()), "text");
+    }
+
+    #[test]
+    fn project_config_rejects_unknown_settings() {
+        let dir = fixture(&[("format.toml", "format = \"xml\"\n"), ("typo.toml", "formt = \"json\"\n")]);
+        assert!(ProjectConfig::load(&dir.path().join("format.toml")).is_err());
+        assert!(ProjectConfig::load(&dir.path().join("typo.toml")).is_err());
+    }
+}
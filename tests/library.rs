@@ -0,0 +1,34 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rcloc::{analyze_path, BlockState, FileAnalyzer, LanguageDatabase, LineType};
+
+#[test]
+fn lines_are_classified_one_at_a_time() {
+    let lang_db = LanguageDatabase::new();
+    let analyzer = FileAnalyzer::new(Arc::clone(lang_db.get_language(Path::new("lib.rs")).unwrap()));
+    let mut state = BlockState::default();
+    
+    assert_eq!(analyzer.classify_line("/* header", &mut state), LineType::Comment);
+    assert!(state.in_comment());
+    assert_eq!(analyzer.classify_line("still header */ fn main() {}", &mut state), LineType::Code);
+    assert!(!state.in_comment());
+    assert_eq!(analyzer.classify_line("// entry", &mut state), LineType::Comment);
+}
+
+#[test]
+fn analyze_path_totals_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.rs"), "// a\nfn a() {}\n\n").unwrap();
+    std::fs::write(dir.path().join("b.py"), "x = 1\n").unwrap();
+    
+    let result = analyze_path(dir.path(), &LanguageDatabase::new());
+    let total = result.total();
+    assert_eq!((total.files, total.blank_lines, total.comment_lines, total.code_lines), (2, 1, 1, 2));
+    let json = result.to_json();
+    assert_eq!(json["Rust"]["code_lines"], 1);
+    assert_eq!(json["SUM"]["files"], 2);
+    
+    let file = analyze_path(&dir.path().join("b.py"), &LanguageDatabase::new());
+    assert_eq!(file.by_language().len(), 1);
+}